@@ -0,0 +1,1180 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::mpsc;
+use std::thread;
+
+use chrono::{DateTime, Duration, FixedOffset, Local};
+
+use crate::caption::{sidecar_image, CaptionTemplate};
+use crate::chapter::add_chapters;
+use crate::contents::add_contents;
+use crate::cover::{cover_image_settings, date_span, Cover, CoverOptions};
+use crate::dates::{ClockOffset, DateSource};
+use crate::encode::{EncodedImage, ImageEncoding, RasterFilter};
+use crate::error::{BookerError, ErrorPolicy, Result};
+use crate::info::DocumentInfo;
+use crate::manifest::Manifest;
+use crate::metadata::{list_input_files, retrieve_image_and_metadata, ImageAndMetadata};
+use crate::outline::OutlineGrouping;
+use crate::page::{PageOptions, PageSettings};
+use crate::pdf::{write_images_to_pdf_file, BookPage, PageContent};
+use crate::render::{render_page, PageRaster};
+use crate::sort::{file_name_key, sort_key, SortOrder};
+
+/// Where the source images of a book come from.
+#[derive(Debug, Clone)]
+pub enum InputSource {
+  /// Every file below this directory, recursively.
+  Directory(PathBuf),
+  /// A single image file.
+  File(PathBuf),
+  /// A single image file with settings of its own.
+  Page(PathBuf, PageOptions)
+}
+
+/// Where the finished PDF is written to.
+pub enum OutputSink {
+  /// Create (or truncate) the file at this path.
+  File(PathBuf),
+  /// Write into an arbitrary writer, e.g. an in-memory buffer.
+  Writer(Box<dyn Write>)
+}
+
+impl OutputSink {
+  /// Creates the output file if needed, hands the writer to `write` and
+  /// returns the number of bytes written. Write errors for a file name it.
+  fn write_with<F: FnOnce(&mut dyn Write) -> Result<()>>(&mut self, write: F) -> Result<u64> {
+    match self {
+      OutputSink::File(path) => {
+        let file = File::create(&path).map_err(|e| BookerError::io(&path, e))?;
+        let mut output = CountingWriter { inner: BufWriter::new(file), count: 0 };
+        write(&mut output)
+          .and_then(|()| output.flush().map_err(|source| BookerError::Output { source }))
+          .map_err(|error| match error {
+            BookerError::Output { source } => BookerError::io(&path, source),
+            error => error
+          })?;
+        Ok(output.count)
+      },
+      OutputSink::Writer(writer) => {
+        let mut output = CountingWriter { inner: writer, count: 0 };
+        write(&mut output)?;
+        output.flush().map_err(|source| BookerError::Output { source })?;
+        Ok(output.count)
+      }
+    }
+  }
+}
+
+/// Passes everything on to `inner`, counting the bytes.
+struct CountingWriter<W: Write> {
+  inner: W,
+  count: u64
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+  fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+    let written = self.inner.write(data)?;
+    self.count += written as u64;
+    Ok(written)
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    self.inner.flush()
+  }
+}
+
+/// Progress notifications emitted while a book is written.
+#[derive(Debug, Clone, Copy)]
+pub enum Progress {
+  /// Page `page` of `total` is about to be rendered.
+  RenderingPage { page: usize, total: usize },
+  /// Page `page` of `total` has been rendered and placed in the book. Pages
+  /// render in parallel, so this is reported in page order, not necessarily
+  /// straight after the page's `RenderingPage`.
+  RenderedPage { page: usize, total: usize },
+  /// Page `page` of `total` is being added to the PDF.
+  WritingPage { page: usize, total: usize },
+  /// The book came out at `size` bytes, over the size limit, and is made
+  /// again at `dpi` and `jpeg_quality`.
+  Shrinking { size: u64, dpi: f64, jpeg_quality: u8 }
+}
+
+/// JPEG qualities tried, in turn, before the resolution is lowered to make a
+/// book fit its size limit.
+const SHRINK_QUALITIES: [u8; 3] = [75, 60, 45];
+
+/// The resolution is never lowered below this to make a book fit.
+const MIN_SHRINK_DPI: f64 = 30.0;
+
+/// A number of bytes, written like `20MB`, `500 kB` or `1.5GiB`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl fmt::Display for ByteSize {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.0 {
+      bytes if bytes >= 1_000_000_000 => write!(f, "{:.1} GB", bytes as f64 / 1e9),
+      bytes if bytes >= 1_000_000 => write!(f, "{:.1} MB", bytes as f64 / 1e6),
+      bytes if bytes >= 1_000 => write!(f, "{:.1} kB", bytes as f64 / 1e3),
+      bytes => write!(f, "{} B", bytes)
+    }
+  }
+}
+
+impl FromStr for ByteSize {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::result::Result<ByteSize, String> {
+    let invalid = || format!("invalid size {:?}, expected e.g. 20MB or 500kB", s);
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let factor = match unit.trim().to_ascii_lowercase().as_str() {
+      "" | "b" => 1.0,
+      "k" | "kb" => 1e3,
+      "m" | "mb" => 1e6,
+      "g" | "gb" => 1e9,
+      "kib" => 1024.0,
+      "mib" => 1024.0 * 1024.0,
+      "gib" => 1024.0 * 1024.0 * 1024.0,
+      _ => return Err(invalid())
+    };
+    let bytes = (number * factor).floor();
+    if !bytes.is_finite() || bytes < 1.0 {
+      return Err(invalid());
+    }
+    Ok(ByteSize(bytes as u64))
+  }
+}
+
+/// Collects the settings for a book.
+///
+/// ```no_run
+/// use wckfa_booker::{BookBuilder, OutputSink};
+///
+/// BookBuilder::new(OutputSink::File("seminar.pdf".into()))
+///   .input_directory("photos/seminar")
+///   .title("Spring Seminar")
+///   .build()
+///   .write()
+///   .unwrap();
+/// ```
+pub struct BookBuilder {
+  inputs: Vec<InputSource>,
+  page_settings: PageSettings,
+  info: DocumentInfo,
+  date_sources: Vec<DateSource>,
+  clock_offsets: Vec<ClockOffset>,
+  sort_order: SortOrder,
+  reverse: bool,
+  on_error: ErrorPolicy,
+  jobs: usize,
+  intermediates: Option<PathBuf>,
+  max_size: Option<ByteSize>,
+  outline: OutlineGrouping,
+  chapter_gap: Option<Duration>,
+  cover: Option<CoverOptions>,
+  contents: bool,
+  caption: Option<CaptionTemplate>,
+  output: OutputSink,
+  progress: Option<Box<dyn FnMut(Progress)>>
+}
+
+impl BookBuilder {
+  pub fn new(output: OutputSink) -> BookBuilder {
+    BookBuilder {
+      inputs: Vec::new(),
+      page_settings: PageSettings::default(),
+      info: DocumentInfo::default(),
+      date_sources: DateSource::DEFAULT_CHAIN.to_vec(),
+      clock_offsets: Vec::new(),
+      sort_order: SortOrder::default(),
+      reverse: false,
+      on_error: ErrorPolicy::default(),
+      jobs: thread::available_parallelism().map_or(1, |n| n.get()),
+      intermediates: None,
+      max_size: None,
+      outline: OutlineGrouping::default(),
+      chapter_gap: None,
+      cover: None,
+      contents: false,
+      caption: None,
+      output,
+      progress: None
+    }
+  }
+
+  /// Adds every file below `dir` to the book.
+  pub fn input_directory<P: Into<PathBuf>>(mut self, dir: P) -> BookBuilder {
+    self.inputs.push(InputSource::Directory(dir.into()));
+    self
+  }
+
+  /// Adds a single image to the book.
+  pub fn input_file<P: Into<PathBuf>>(mut self, file: P) -> BookBuilder {
+    self.inputs.push(InputSource::File(file.into()));
+    self
+  }
+
+  /// Adds a single image to the book with settings that apply only to its page.
+  pub fn input_page<P: Into<PathBuf>>(mut self, file: P, options: PageOptions) -> BookBuilder {
+    self.inputs.push(InputSource::Page(file.into(), options));
+    self
+  }
+
+  /// Adds the pages listed in `manifest`, keeps them in the listed order,
+  /// bookmarks only its sections and takes the title from it if it has one.
+  pub fn manifest(mut self, manifest: Manifest) -> BookBuilder {
+    for page in manifest.pages {
+      self.inputs.push(InputSource::Page(page.image, page.options));
+    }
+    if let Some(title) = manifest.title {
+      self.info.title = title;
+    }
+    self.sort_order = SortOrder::Manifest;
+    // The manifest's sections make the bookmarks
+    self.outline = OutlineGrouping::None;
+    self
+  }
+
+  pub fn page_settings(mut self, settings: PageSettings) -> BookBuilder {
+    self.page_settings = settings;
+    self
+  }
+
+  /// Sets the title stored in the PDF document information.
+  pub fn title<S: Into<String>>(mut self, title: S) -> BookBuilder {
+    self.info.title = title.into();
+    self
+  }
+
+  /// Sets the author stored in the PDF document information.
+  pub fn author<S: Into<String>>(mut self, author: S) -> BookBuilder {
+    self.info.author = Some(author.into());
+    self
+  }
+
+  /// Sets the subject stored in the PDF document information.
+  pub fn subject<S: Into<String>>(mut self, subject: S) -> BookBuilder {
+    self.info.subject = Some(subject.into());
+    self
+  }
+
+  /// Sets the comma separated keywords stored in the PDF document information.
+  pub fn keywords<S: Into<String>>(mut self, keywords: S) -> BookBuilder {
+    self.info.keywords = Some(keywords.into());
+    self
+  }
+
+  /// Sets who or what made the book, stored as the PDF creator.
+  pub fn creator<S: Into<String>>(mut self, creator: S) -> BookBuilder {
+    self.info.creator = Some(creator.into());
+    self
+  }
+
+  /// Sets the creation date stored in the PDF. Defaults to the capture date
+  /// of the earliest photo in the book.
+  pub fn creation_date(mut self, date: DateTime<FixedOffset>) -> BookBuilder {
+    self.info.creation_date = Some(date);
+    self
+  }
+
+  /// Sets the places a capture date is looked for, in order of preference.
+  /// Defaults to [`DateSource::DEFAULT_CHAIN`].
+  pub fn date_sources<I: IntoIterator<Item = DateSource>>(mut self, sources: I) -> BookBuilder {
+    self.date_sources = sources.into_iter().collect();
+    self
+  }
+
+  /// Corrects the clock of one camera model before the images are sorted.
+  pub fn clock_offset(mut self, offset: ClockOffset) -> BookBuilder {
+    self.clock_offsets.push(offset);
+    self
+  }
+
+  /// Sets the page order. Defaults to [`SortOrder::ExifDate`].
+  pub fn sort_order(mut self, order: SortOrder) -> BookBuilder {
+    self.sort_order = order;
+    self
+  }
+
+  /// Reverses the page order, e.g. to put the newest photos first.
+  pub fn reverse(mut self, reverse: bool) -> BookBuilder {
+    self.reverse = reverse;
+    self
+  }
+
+  /// Chooses what happens to input files that cannot be used. Defaults to [`ErrorPolicy::Fail`].
+  pub fn on_error(mut self, policy: ErrorPolicy) -> BookBuilder {
+    self.on_error = policy;
+    self
+  }
+
+  /// Sets how many pages are rendered at the same time. Defaults to the number
+  /// of CPUs; each job holds one decoded image in memory.
+  pub fn jobs(mut self, jobs: usize) -> BookBuilder {
+    self.jobs = jobs.max(1);
+    self
+  }
+
+  /// Also saves each compressed page image to `dir` as `page-NNN.jpg`, or
+  /// `page-NNN.png` for losslessly stored pages, for debugging.
+  pub fn keep_intermediates<P: Into<PathBuf>>(mut self, dir: P) -> BookBuilder {
+    self.intermediates = Some(dir.into());
+    self
+  }
+
+  /// Sets how the bookmarks are grouped by capture date. Defaults to
+  /// [`OutlineGrouping::Day`].
+  pub fn outline(mut self, grouping: OutlineGrouping) -> BookBuilder {
+    self.outline = grouping;
+    self
+  }
+
+  /// Starts a new chapter, with a divider page, wherever consecutive photos
+  /// were taken more than `gap` apart.
+  pub fn chapter_gap(mut self, gap: Duration) -> BookBuilder {
+    self.chapter_gap = Some(gap);
+    self
+  }
+
+  /// Starts the book with a cover page showing the title, author and the
+  /// days the photos were taken on, and whatever `options` add.
+  pub fn cover(mut self, options: CoverOptions) -> BookBuilder {
+    self.cover = Some(options);
+    self
+  }
+
+  /// Adds table of contents pages, after the cover if there is one, that
+  /// link to the chapters or top level date bookmarks and the sections.
+  pub fn table_of_contents(mut self, contents: bool) -> BookBuilder {
+    self.contents = contents;
+    self
+  }
+
+  /// Prints a caption made from `template` under each photo that has no
+  /// caption of its own, keeping room for it below the image.
+  pub fn caption(mut self, template: CaptionTemplate) -> BookBuilder {
+    self.caption = Some(template);
+    self
+  }
+
+  /// Keeps the PDF within `limit`. A book that comes out larger is made again
+  /// with lower JPEG quality, then lower resolution, until it fits.
+  pub fn max_size(mut self, limit: ByteSize) -> BookBuilder {
+    self.max_size = Some(limit);
+    self
+  }
+
+  /// Registers a callback that is told about each page as it is processed.
+  pub fn on_progress<F: FnMut(Progress) + 'static>(mut self, callback: F) -> BookBuilder {
+    self.progress = Some(Box::new(callback));
+    self
+  }
+
+  pub fn build(self) -> Book {
+    Book {
+      inputs: self.inputs,
+      page_settings: self.page_settings,
+      info: self.info,
+      date_sources: self.date_sources,
+      clock_offsets: self.clock_offsets,
+      sort_order: self.sort_order,
+      reverse: self.reverse,
+      on_error: self.on_error,
+      jobs: self.jobs,
+      intermediates: self.intermediates,
+      max_size: self.max_size,
+      outline: self.outline,
+      chapter_gap: self.chapter_gap,
+      cover: self.cover,
+      contents: self.contents,
+      caption: self.caption,
+      output: self.output,
+      progress: self.progress
+    }
+  }
+}
+
+/// A fully configured book, ready to be written.
+pub struct Book {
+  inputs: Vec<InputSource>,
+  page_settings: PageSettings,
+  info: DocumentInfo,
+  date_sources: Vec<DateSource>,
+  clock_offsets: Vec<ClockOffset>,
+  sort_order: SortOrder,
+  reverse: bool,
+  on_error: ErrorPolicy,
+  jobs: usize,
+  intermediates: Option<PathBuf>,
+  max_size: Option<ByteSize>,
+  outline: OutlineGrouping,
+  chapter_gap: Option<Duration>,
+  cover: Option<CoverOptions>,
+  contents: bool,
+  caption: Option<CaptionTemplate>,
+  output: OutputSink,
+  progress: Option<Box<dyn FnMut(Progress)>>
+}
+
+/// An input file that did not make it into the book as a photo.
+#[derive(Debug)]
+pub struct SkippedImage {
+  pub path: String,
+  pub error: BookerError
+}
+
+/// Summary of a finished book.
+#[derive(Debug)]
+pub struct BookReport {
+  /// Number of pages in the PDF.
+  pub pages: usize,
+  /// Files that were left out or replaced by a placeholder, with the reason.
+  pub skipped: Vec<SkippedImage>,
+  /// Size of the PDF.
+  pub size: ByteSize,
+  /// Resolution the pages were rendered at, lower than configured if the book
+  /// had to shrink to fit its size limit.
+  pub dpi: f64,
+  /// JPEG quality the pages were stored with, also lowered to fit the size limit.
+  pub jpeg_quality: u8
+}
+
+/// What a render worker reports about the slot at an index.
+enum RenderEvent {
+  /// The worker has claimed the slot and starts rendering it.
+  Started(usize),
+  /// The outcome of rendering the slot, `None` for a placeholder, and the
+  /// raster it rendered if rasters are kept.
+  Finished(usize, Option<Result<(EncodedImage, bool)>>, Option<PageRaster>)
+}
+
+/// Page rasters kept between attempts at fitting the size limit, so that
+/// attempts that only lower the JPEG quality need not decode the photos again.
+struct RasterCache {
+  /// Resolution the rasters were rendered at.
+  dpi: f64,
+  /// The raster of each slot, once rendered.
+  rasters: Vec<Option<PageRaster>>
+}
+
+/// A page of the book before it is rendered.
+enum Slot {
+  Image(ImageAndMetadata, PageOptions),
+  /// The path of the unusable file and the page standing in for it.
+  Placeholder(String, PageContent, PageOptions)
+}
+
+impl Book {
+  /// Gathers the metadata of every input image and puts the pages in the
+  /// book's [`SortOrder`]. Capture times are compared after clock corrections.
+  ///
+  /// Files that cannot be read are handled according to the book's
+  /// [`ErrorPolicy`] and recorded in `skipped`. When sorting by date,
+  /// placeholders for them are put after all dated images.
+  fn collect_pages(&self, skipped: &mut Vec<SkippedImage>) -> Result<Vec<Slot>> {
+    let mut files: Vec<(String, PageOptions)> = Vec::new();
+    for input in &self.inputs {
+      match input {
+        InputSource::Directory(dir) => {
+          let mut listing = list_input_files(&dir.display().to_string())?;
+          // Caption text files next to the photos are not pages themselves
+          let listed: HashSet<String> = listing.iter().cloned().collect();
+          listing.retain(|file| sidecar_image(file).is_none_or(|image| !listed.contains(image)));
+          if self.sort_order == SortOrder::Manifest {
+            listing.sort_by_cached_key(|path| file_name_key(path));
+          }
+          files.extend(listing.into_iter().map(|file| (file, PageOptions::default())));
+        },
+        InputSource::File(file) => files.push((file.display().to_string(), PageOptions::default())),
+        InputSource::Page(file, options) => files.push((file.display().to_string(), options.clone()))
+      }
+    }
+
+    let mut slots: Vec<Slot> = Vec::new();
+    for (file, mut options) in files {
+      let image = retrieve_image_and_metadata(&file, &self.date_sources).and_then(|mut imamd| {
+        let correction = self.clock_offsets.iter()
+          .find(|c| imamd.camera_model.as_deref() == Some(c.camera_model.as_str()));
+        if let Some(correction) = correction {
+          imamd.shift_clock(correction.offset)?;
+        }
+        Ok(imamd)
+      });
+      match image {
+        Ok(imamd) => {
+          // A caption from the manifest wins over the template
+          if options.caption.is_none() {
+            options.caption = self.caption.as_ref().and_then(|template| template.render(&imamd));
+          }
+          slots.push(Slot::Image(imamd, options));
+        },
+        Err(error) => {
+          let path = file.clone();
+          if let Some(placeholder) = self.recover(file, error, skipped)? {
+            slots.push(Slot::Placeholder(path, placeholder, options));
+          }
+        }
+      }
+    }
+
+    slots.sort_by_cached_key(|slot| match slot {
+      Slot::Image(image, _) => sort_key(self.sort_order, Some(image), &image.path),
+      Slot::Placeholder(path, ..) => sort_key(self.sort_order, None, path)
+    });
+    if self.reverse {
+      slots.reverse();
+    }
+    if self.sort_order == SortOrder::ExifDate {
+      // Placeholders have no date to sort by, so they go after all dated images
+      slots.sort_by_key(|slot| matches!(slot, Slot::Placeholder(..)));
+    }
+
+    Ok(slots)
+  }
+
+  /// Applies the error policy to a failed file. Returns the placeholder page
+  /// that should take its place, if any.
+  fn recover(&self, path: String, error: BookerError, skipped: &mut Vec<SkippedImage>) -> Result<Option<PageContent>> {
+    let placeholder = match self.on_error {
+      ErrorPolicy::Fail => return Err(error),
+      ErrorPolicy::Skip => None,
+      ErrorPolicy::Placeholder => Some(PageContent::Missing { path: path.clone(), reason: error.to_string() })
+    };
+    skipped.push(SkippedImage { path, error });
+    Ok(placeholder)
+  }
+
+  /// Runs the whole pipeline: reads the inputs, renders each page and writes
+  /// the PDF, shrinking it as often as needed to stay within the size limit.
+  pub fn write(mut self) -> Result<BookReport> {
+    self.page_settings.check()?;
+    let mut skipped = Vec::new();
+    let slots = self.collect_pages(&mut skipped)?;
+
+    if let Some(dir) = &self.intermediates {
+      fs::create_dir_all(dir).map_err(|e| BookerError::io(dir, e))?;
+    }
+
+    let mut info = self.info.clone();
+    if info.creation_date.is_none() {
+      info.creation_date = slots.iter()
+        .filter_map(|slot| match slot {
+          Slot::Image(image, _) => Some(image),
+          Slot::Placeholder(..) => None
+        })
+        .min_by_key(|image| image.instant())
+        .map(|image| image.date_zoned.unwrap_or_else(|| {
+          let local = image.instant().with_timezone(&Local);
+          local.with_timezone(local.offset())
+        }));
+    }
+
+    let mut settings = self.page_settings;
+    let mut rasters: Option<RasterCache> = None;
+    let (size, total_pages, render_skipped) = loop {
+      // Rasters are only worth keeping while a retry may lower just the JPEG quality
+      let keep_rasters = self.max_size.is_some()
+        && settings.encoding != ImageEncoding::Flate
+        && SHRINK_QUALITIES.iter().any(|&quality| quality < settings.jpeg_quality);
+      rasters = match rasters.take() {
+        Some(cache) if cache.dpi == settings.dpi() => Some(cache),
+        _ if keep_rasters => Some(RasterCache { dpi: settings.dpi(), rasters: Vec::new() }),
+        _ => None
+      };
+
+      let mut render_skipped = Vec::new();
+      let mut pages = self.render_pages(&slots, &settings, rasters.as_mut(), &mut render_skipped)?;
+      if let Some(gap) = self.chapter_gap {
+        pages = add_chapters(pages, gap);
+      }
+      if let Some(options) = &self.cover {
+        let cover = cover_page(options, &info, &pages, &settings)?;
+        pages.insert(0, cover);
+      }
+      if self.contents {
+        let position = if self.cover.is_some() { 1 } else { 0 };
+        pages = add_contents(pages, position, self.outline, &settings);
+      }
+
+      let total_pages = pages.len();
+      let uses_jpeg = pages.iter().any(|page| match &page.content {
+        PageContent::Raster(encoded) | PageContent::Cover(Cover { image: Some(encoded), .. }) => encoded.filter == RasterFilter::Dct,
+        _ => false
+      });
+      let outline = self.outline;
+      let progress = &mut self.progress;
+      let on_page = |page| {
+        if let Some(callback) = progress.as_mut() {
+          callback(Progress::WritingPage { page, total: total_pages });
+        }
+      };
+      let limit = match self.max_size {
+        Some(limit) => limit,
+        // Without a size limit the first attempt is final, so it goes straight to the output
+        None => {
+          let size = self.output.write_with(|output| write_images_to_pdf_file(output, &info, pages, &settings, outline, on_page))?;
+          break (size, total_pages, render_skipped);
+        }
+      };
+
+      let mut pdf = Vec::new();
+      write_images_to_pdf_file(&mut pdf, &info, pages, &settings, outline, on_page)?;
+      let size = pdf.len() as u64;
+      if size <= limit.0 {
+        let size = self.output.write_with(|output| output.write_all(&pdf).map_err(|source| BookerError::Output { source }))?;
+        break (size, total_pages, render_skipped);
+      }
+      drop(pdf);
+      settings = smaller_settings(&settings, size, limit.0, uses_jpeg)
+        .ok_or(BookerError::TooLarge { size: ByteSize(size), limit })?;
+      self.notify(Progress::Shrinking { size, dpi: settings.dpi(), jpeg_quality: settings.jpeg_quality });
+    };
+    skipped.extend(render_skipped);
+
+    Ok(BookReport {
+      pages: total_pages,
+      skipped,
+      size: ByteSize(size),
+      dpi: settings.dpi(),
+      jpeg_quality: settings.jpeg_quality
+    })
+  }
+
+  /// Renders and compresses the image slots on up to `jobs` threads and
+  /// returns the pages in slot order. Each thread holds one decoded image at a
+  /// time. Pages are reported as a worker starts on them; finished pages are
+  /// reported, and failures recovered, in page order as results come in.
+  ///
+  /// With `rasters`, pages rendered before at the same resolution are only
+  /// compressed again, and newly rendered pages are added to it.
+  fn render_pages(&mut self, slots: &[Slot], settings: &PageSettings, rasters: Option<&mut RasterCache>,
+                  skipped: &mut Vec<SkippedImage>) -> Result<Vec<BookPage>> {
+    let total_images = slots.len();
+    let next_slot = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    let keep_rasters = rasters.is_some();
+    let cached: &[Option<PageRaster>] = rasters.as_deref().map_or(&[], |cache| &cache.rasters);
+    let mut fresh = Vec::new();
+
+    let pages = thread::scope(|scope| {
+      for _ in 0..self.jobs.min(total_images) {
+        let sender = sender.clone();
+        let next_slot = &next_slot;
+        scope.spawn(move || loop {
+          let index = next_slot.fetch_add(1, atomic::Ordering::Relaxed);
+          let slot = match slots.get(index) {
+            Some(slot) => slot,
+            None => break
+          };
+          if sender.send(RenderEvent::Started(index)).is_err() {
+            break;
+          }
+          let (rendered, raster) = match (slot, cached.get(index)) {
+            (Slot::Image(image, _), Some(Some(raster))) => (Some(encode_raster(image, raster, settings)), None),
+            (Slot::Image(image, options), _) => match render_raster(image, settings, options) {
+              Ok(raster) => {
+                let encoded = encode_raster(image, &raster, settings);
+                (Some(encoded), if keep_rasters { Some(raster) } else { None })
+              },
+              Err(error) => (Some(Err(error)), None)
+            },
+            (Slot::Placeholder(..), _) => (None, None)
+          };
+          if sender.send(RenderEvent::Finished(index, rendered, raster)).is_err() {
+            break;
+          }
+        });
+      }
+      drop(sender);
+
+      // Results arrive in any order; hold on to them until all earlier pages are placed
+      let mut pages: Vec<BookPage> = Vec::with_capacity(total_images);
+      let mut finished = BTreeMap::new();
+      let mut next_index = 0;
+      for event in receiver.iter() {
+        let (index, rendered) = match event {
+          RenderEvent::Started(index) => {
+            self.notify(Progress::RenderingPage { page: index + 1, total: total_images });
+            continue;
+          },
+          RenderEvent::Finished(index, rendered, raster) => {
+            if let Some(raster) = raster {
+              fresh.push((index, raster));
+            }
+            (index, rendered)
+          }
+        };
+        finished.insert(index, rendered);
+        while let Some(rendered) = finished.remove(&next_index) {
+          if let Err(error) = self.place_page(&slots[next_index], rendered, &mut pages, skipped) {
+            // Stop the workers from starting on any further images
+            next_slot.store(total_images, atomic::Ordering::Relaxed);
+            return Err(error);
+          }
+          self.notify(Progress::RenderedPage { page: next_index + 1, total: total_images });
+          next_index += 1;
+        }
+      }
+      Ok(pages)
+    })?;
+
+    if let Some(cache) = rasters {
+      cache.rasters.resize_with(total_images, || None);
+      for (index, raster) in fresh {
+        cache.rasters[index] = Some(raster);
+      }
+    }
+    Ok(pages)
+  }
+
+  /// Adds the page for `slot` to `pages`, given the outcome of rendering it.
+  fn place_page(&self, slot: &Slot, rendered: Option<Result<(EncodedImage, bool)>>,
+                pages: &mut Vec<BookPage>, skipped: &mut Vec<SkippedImage>) -> Result<()> {
+    let (image, options) = match slot {
+      Slot::Image(image, options) => (image, options),
+      Slot::Placeholder(_, placeholder, options) => {
+        pages.push(BookPage { content: placeholder.clone(), section: options.section.clone(), caption: options.caption.clone(), sideways: false, date: None });
+        return Ok(());
+      }
+    };
+
+    match rendered {
+      // Only placeholders come back without a rendering
+      None => {},
+      Some(Ok((encoded, sideways))) => {
+        // Pages are numbered by their final position, which differs from the
+        // slot index once files have been skipped.
+        if let Some(dir) = &self.intermediates {
+          encoded.save(&dir.join(format!("page-{:03}", pages.len() + 1)))?;
+        }
+        pages.push(BookPage {
+          content: PageContent::Raster(encoded),
+          section: options.section.clone(),
+          caption: options.caption.clone(),
+          sideways,
+          date: Some(image.capture_time())
+        });
+      },
+      Some(Err(error)) => {
+        if let Some(placeholder) = self.recover(image.path.clone(), error, skipped)? {
+          pages.push(BookPage { content: placeholder, section: options.section.clone(), caption: options.caption.clone(), sideways: false, date: None });
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn notify(&mut self, event: Progress) {
+    if let Some(callback) = self.progress.as_mut() {
+      callback(event);
+    }
+  }
+}
+
+/// The next settings to try for a book that came out at `size` bytes, over
+/// `limit`: a lower JPEG quality while there is one to try, if any page was
+/// stored as JPEG, then a lower resolution. `None` once nothing is left to lower.
+fn smaller_settings(settings: &PageSettings, size: u64, limit: u64, uses_jpeg: bool) -> Option<PageSettings> {
+  let mut smaller = *settings;
+  if uses_jpeg {
+    if let Some(&quality) = SHRINK_QUALITIES.iter().find(|&&q| q < settings.jpeg_quality) {
+      smaller.jpeg_quality = quality;
+      return Some(smaller);
+    }
+  }
+
+  let dpi = settings.dpi();
+  // The resolution is stored as a scale, so it comes back with rounding errors
+  if dpi.round() <= MIN_SHRINK_DPI {
+    return None;
+  }
+  // The size goes roughly with the number of pixels, so with the square of the resolution
+  let scale = ((limit as f64 / size as f64).sqrt() * 0.95).min(0.9);
+  Some(smaller.with_dpi((dpi * scale).max(MIN_SHRINK_DPI)))
+}
+
+/// The cover page for a book of `pages`.
+fn cover_page(options: &CoverOptions, info: &DocumentInfo, pages: &[BookPage], settings: &PageSettings) -> Result<BookPage> {
+  let image = match &options.image {
+    Some(path) => {
+      // Only the orientation is wanted, which the modification time never stands in the way of
+      let image = retrieve_image_and_metadata(&path.display().to_string(), &[DateSource::Mtime])?;
+      let settings = cover_image_settings(settings);
+      let raster = render_raster(&image, &settings, &PageOptions::default())?;
+      Some(encode_raster(&image, &raster, &settings)?.0)
+    },
+    None => None
+  };
+  let first = pages.iter().filter_map(|page| page.date.map(|date| date.local)).min();
+  let last = pages.iter().filter_map(|page| page.date.map(|date| date.local)).max();
+  let dates = first.zip(last).map(|(first, last)| date_span(first.date(), last.date()));
+
+  let cover = Cover { title: info.title.clone(), subtitle: options.subtitle.clone(), author: info.author.clone(), dates, image };
+  Ok(BookPage { content: PageContent::Cover(cover), section: None, caption: None, sideways: false, date: None })
+}
+
+/// Renders one page, leaving room for its caption.
+fn render_raster(image: &ImageAndMetadata, settings: &PageSettings, options: &PageOptions) -> Result<PageRaster> {
+  let settings = match &options.caption {
+    Some(caption) => settings.with_caption_space(caption),
+    None => *settings
+  };
+  render_page(image, &settings, options)
+}
+
+/// Compresses a rendered page. Also returns whether the page is turned sideways.
+fn encode_raster(image: &ImageAndMetadata, raster: &PageRaster, settings: &PageSettings) -> Result<(EncodedImage, bool)> {
+  let encoded = EncodedImage::encode(&raster.image, settings.encoding, settings.jpeg_quality, Path::new(&image.path))?;
+  Ok((encoded, raster.sideways))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  use printpdf::Mm;
+
+  use crate::page::{LandscapePolicy, Orientation, PageSize};
+  use crate::test_support::{write_png, SharedBuffer, TempDir};
+
+  /// Writes small photos with these names, which carry their capture times.
+  fn photos(dir: &TempDir, names: &[&str]) {
+    for name in names {
+      write_png(&dir.join(name), 60, 40, [200, 80, 40]);
+    }
+  }
+
+  /// The file names of the slots, in book order.
+  fn slot_names(slots: &[Slot]) -> Vec<String> {
+    slots.iter().map(|slot| {
+      let path = match slot {
+        Slot::Image(image, _) => &image.path,
+        Slot::Placeholder(path, ..) => path
+      };
+      Path::new(path).file_name().unwrap().to_string_lossy().into_owned()
+    }).collect()
+  }
+
+  #[test]
+  fn writes_one_page_per_photo() {
+    let dir = TempDir::new();
+    photos(&dir, &["IMG_20240316_110000.png", "IMG_20240316_090000.png", "IMG_20240316_100000.png"]);
+    let output = SharedBuffer::default();
+    let report = BookBuilder::new(OutputSink::Writer(Box::new(output.clone())))
+      .input_directory(dir.path())
+      .title("Spring Seminar")
+      .build()
+      .write()
+      .unwrap();
+    assert_eq!(report.pages, 3);
+    assert!(report.skipped.is_empty());
+    assert_eq!(report.size.0, output.bytes().len() as u64);
+
+    let document = lopdf::Document::load_mem(&output.bytes()).unwrap();
+    assert_eq!(document.get_pages().len(), 3);
+    let info_id = document.trailer.get(b"Info").unwrap().as_reference().unwrap();
+    let title = document.get_dictionary(info_id).unwrap().get(b"Title").unwrap().as_str().unwrap();
+    assert_eq!(title, b"Spring Seminar");
+  }
+
+  #[test]
+  fn sorts_pages_by_capture_date() {
+    let dir = TempDir::new();
+    photos(&dir, &["IMG_20240316_110000.png", "IMG_20240316_090000.png", "IMG_20240316_100000.png"]);
+    let book = BookBuilder::new(OutputSink::Writer(Box::new(std::io::sink()))).input_directory(dir.path()).build();
+    let slots = book.collect_pages(&mut Vec::new()).unwrap();
+    assert_eq!(slot_names(&slots), vec!["IMG_20240316_090000.png", "IMG_20240316_100000.png", "IMG_20240316_110000.png"]);
+
+    let book = BookBuilder::new(OutputSink::Writer(Box::new(std::io::sink()))).input_directory(dir.path()).reverse(true).build();
+    let slots = book.collect_pages(&mut Vec::new()).unwrap();
+    assert_eq!(slot_names(&slots), vec!["IMG_20240316_110000.png", "IMG_20240316_100000.png", "IMG_20240316_090000.png"]);
+  }
+
+  /// Writes `count` photos taken a minute apart, starting at 09:00.
+  fn minute_photos(dir: &TempDir, count: u32) -> Vec<String> {
+    let names: Vec<String> = (0..count).map(|n| format!("IMG_20240316_09{:02}00.png", n)).collect();
+    for (n, name) in names.iter().enumerate() {
+      write_png(&dir.join(name), 120, 80, [(n * 6) as u8, 80, 40]);
+    }
+    names
+  }
+
+  /// A book of the photos in `dir` that records its progress events.
+  fn recorded_book(dir: &TempDir, jobs: usize) -> (Book, Rc<RefCell<Vec<Progress>>>) {
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let recorded = events.clone();
+    let book = BookBuilder::new(OutputSink::Writer(Box::new(std::io::sink())))
+      .input_directory(dir.path())
+      .date_sources(vec![DateSource::Filename])
+      .page_settings(PageSettings::new(PageSize::Custom(Mm(40.0), Mm(60.0)), Orientation::Portrait, 75.0))
+      .jobs(jobs)
+      .on_progress(move |event| recorded.borrow_mut().push(event))
+      .build();
+    (book, events)
+  }
+
+  #[test]
+  fn parallel_rendering_keeps_page_order() {
+    let dir = TempDir::new();
+    minute_photos(&dir, 12);
+    let (mut book, events) = recorded_book(&dir, 4);
+    let slots = book.collect_pages(&mut Vec::new()).unwrap();
+    let settings = book.page_settings;
+    let pages = book.render_pages(&slots, &settings, None, &mut Vec::new()).unwrap();
+
+    let dates: Vec<chrono::NaiveDateTime> = pages.iter().map(|page| page.date.unwrap().local).collect();
+    let mut sorted = dates.clone();
+    sorted.sort();
+    assert_eq!(dates.len(), 12);
+    assert_eq!(dates, sorted);
+
+    let rendered: Vec<usize> = events.borrow().iter().filter_map(|event| match event {
+      Progress::RenderedPage { page, total: 12 } => Some(*page),
+      _ => None
+    }).collect();
+    assert_eq!(rendered, (1..=12).collect::<Vec<usize>>());
+  }
+
+  #[test]
+  fn a_failing_page_stops_the_workers() {
+    let dir = TempDir::new();
+    let names = minute_photos(&dir, 40);
+    std::fs::write(dir.join(&names[1]), b"not a png").unwrap();
+    let (mut book, events) = recorded_book(&dir, 2);
+    let slots = book.collect_pages(&mut Vec::new()).unwrap();
+    let settings = book.page_settings;
+
+    let error = book.render_pages(&slots, &settings, None, &mut Vec::new()).unwrap_err();
+    assert_eq!(error.path().unwrap().file_name().unwrap().to_string_lossy(), names[1]);
+    let started = events.borrow().iter().filter(|event| matches!(event, Progress::RenderingPage { .. })).count();
+    assert!(started < names.len(), "all {} pages were started", started);
+    assert!(!events.borrow().iter().any(|event| matches!(event, Progress::RenderedPage { page: 2, .. })));
+  }
+
+  /// Width and height of each page's media box, in points.
+  fn page_boxes(pdf: &[u8]) -> Vec<(f64, f64)> {
+    let document = lopdf::Document::load_mem(pdf).unwrap();
+    document.get_pages().values().map(|&id| {
+      let media_box = document.get_dictionary(id).unwrap().get(b"MediaBox").unwrap().as_array().unwrap();
+      let number = |n: usize| media_box[n].as_f64().or_else(|_| media_box[n].as_i64().map(|v| v as f64)).unwrap();
+      (number(2) - number(0), number(3) - number(1))
+    }).collect()
+  }
+
+  #[test]
+  fn landscape_photos_get_sideways_pages() {
+    let dir = TempDir::new();
+    write_png(&dir.join("IMG_20240316_090000.png"), 60, 40, [200, 80, 40]);
+    write_png(&dir.join("IMG_20240316_100000.png"), 40, 60, [200, 80, 40]);
+    let settings = PageSettings { landscape: LandscapePolicy::LandscapePage, ..PageSettings::default() };
+    let output = SharedBuffer::default();
+    BookBuilder::new(OutputSink::Writer(Box::new(output.clone())))
+      .input_directory(dir.path())
+      .page_settings(settings)
+      .build()
+      .write()
+      .unwrap();
+    let boxes = page_boxes(&output.bytes());
+    let (width, height) = boxes[0];
+    assert!(width > height);
+    assert_eq!(boxes[1], (height, width));
+  }
+
+  /// Two dated photos, an undated one and a broken file that only fails
+  /// when it is decoded. The books below ignore modification times, so the
+  /// undated photo cannot be placed.
+  fn photos_with_failures(dir: &TempDir) {
+    photos(dir, &["IMG_20240316_090000.png", "IMG_20240316_110000.png", "scan.png"]);
+    std::fs::write(dir.join("IMG_20240316_100000.png"), b"not a png").unwrap();
+  }
+
+  fn write_with_policy(dir: &TempDir, policy: ErrorPolicy) -> Result<BookReport> {
+    BookBuilder::new(OutputSink::Writer(Box::new(std::io::sink())))
+      .input_directory(dir.path())
+      .date_sources(vec![DateSource::Filename])
+      .on_error(policy)
+      .build()
+      .write()
+  }
+
+  fn skipped_names(report: &BookReport) -> Vec<String> {
+    let mut names: Vec<String> = report.skipped.iter()
+      .map(|skipped| Path::new(&skipped.path).file_name().unwrap().to_string_lossy().into_owned())
+      .collect();
+    names.sort();
+    names
+  }
+
+  #[test]
+  fn skip_policy_leaves_out_failed_files() {
+    let dir = TempDir::new();
+    photos_with_failures(&dir);
+    let report = write_with_policy(&dir, ErrorPolicy::Skip).unwrap();
+    assert_eq!(report.pages, 2);
+    assert_eq!(skipped_names(&report), vec!["IMG_20240316_100000.png", "scan.png"]);
+  }
+
+  #[test]
+  fn placeholder_policy_keeps_a_page_for_failed_files() {
+    let dir = TempDir::new();
+    photos_with_failures(&dir);
+    let report = write_with_policy(&dir, ErrorPolicy::Placeholder).unwrap();
+    assert_eq!(report.pages, 4);
+    assert_eq!(skipped_names(&report), vec!["IMG_20240316_100000.png", "scan.png"]);
+
+    let book = BookBuilder::new(OutputSink::Writer(Box::new(std::io::sink())))
+      .input_directory(dir.path())
+      .date_sources(vec![DateSource::Filename])
+      .on_error(ErrorPolicy::Placeholder)
+      .build();
+    let slots = book.collect_pages(&mut Vec::new()).unwrap();
+    assert_eq!(slot_names(&slots).last().unwrap(), "scan.png");
+  }
+
+  #[test]
+  fn fail_policy_returns_the_first_error() {
+    let dir = TempDir::new();
+    photos(&dir, &["IMG_20240316_090000.png", "scan.png"]);
+    match write_with_policy(&dir, ErrorPolicy::Fail).unwrap_err() {
+      BookerError::MissingDate { path } => assert_eq!(path.file_name().unwrap(), "scan.png"),
+      other => panic!("unexpected error {:?}", other)
+    }
+
+    let dir = TempDir::new();
+    photos_with_failures(&dir);
+    std::fs::remove_file(dir.join("scan.png")).unwrap();
+    let error = write_with_policy(&dir, ErrorPolicy::Fail).unwrap_err();
+    assert_eq!(error.path().unwrap().file_name().unwrap(), "IMG_20240316_100000.png");
+  }
+
+  /// A writer that refuses every byte.
+  struct BrokenPipe;
+
+  impl Write for BrokenPipe {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+      Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "pipe closed"))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn output_errors_are_reported_as_such() {
+    let dir = TempDir::new();
+    photos(&dir, &["IMG_20240316_090000.png"]);
+    let error = BookBuilder::new(OutputSink::Writer(Box::new(BrokenPipe))).input_directory(dir.path()).build().write().unwrap_err();
+    match error {
+      BookerError::Output { source } => assert_eq!(source.kind(), std::io::ErrorKind::BrokenPipe),
+      other => panic!("unexpected error {:?}", other)
+    }
+  }
+
+  #[test]
+  fn writes_to_a_file() {
+    let dir = TempDir::new();
+    photos(&dir, &["IMG_20240316_090000.png"]);
+    let output = dir.join("out").join("book.pdf");
+    std::fs::create_dir(dir.join("out")).unwrap();
+    let report = BookBuilder::new(OutputSink::File(output.clone()))
+      .input_file(dir.join("IMG_20240316_090000.png"))
+      .build()
+      .write()
+      .unwrap();
+    assert_eq!(std::fs::metadata(&output).unwrap().len(), report.size.0);
+
+    // Under a size limit the book is only written once it fits
+    let report = BookBuilder::new(OutputSink::File(output.clone()))
+      .input_file(dir.join("IMG_20240316_090000.png"))
+      .max_size(ByteSize(10_000_000))
+      .build()
+      .write()
+      .unwrap();
+    assert_eq!(std::fs::metadata(&output).unwrap().len(), report.size.0);
+    assert_eq!(lopdf::Document::load(&output).unwrap().get_pages().len(), 1);
+  }
+
+  #[test]
+  fn unwritable_files_are_named_in_the_error() {
+    let dir = TempDir::new();
+    photos(&dir, &["IMG_20240316_090000.png"]);
+    let output = dir.join("missing").join("book.pdf");
+    match BookBuilder::new(OutputSink::File(output.clone())).input_directory(dir.path()).build().write().unwrap_err() {
+      BookerError::Io { path, .. } => assert_eq!(path, output),
+      other => panic!("unexpected error {:?}", other)
+    }
+  }
+
+  #[test]
+  fn quality_retries_reuse_the_rendered_pages() {
+    let dir = TempDir::new();
+    let names = minute_photos(&dir, 3);
+    let shrinking = Rc::new(RefCell::new(Vec::new()));
+    let recorded = shrinking.clone();
+    let sources: Vec<PathBuf> = names.iter().map(|name| dir.join(name)).collect();
+    let settings = PageSettings { encoding: ImageEncoding::Jpeg, ..PageSettings::default() };
+    let error = BookBuilder::new(OutputSink::Writer(Box::new(std::io::sink())))
+      .input_directory(dir.path())
+      .date_sources(vec![DateSource::Filename])
+      .page_settings(settings)
+      .max_size(ByteSize(1_000))
+      .on_progress(move |event| {
+        if let Progress::Shrinking { dpi, jpeg_quality, .. } = event {
+          // Once rendered, the photos are only needed again at a lower resolution
+          for source in &sources {
+            std::fs::remove_file(source).ok();
+          }
+          recorded.borrow_mut().push((dpi.round(), jpeg_quality));
+        }
+      })
+      .build()
+      .write()
+      .unwrap_err();
+
+    assert!(matches!(error, BookerError::Decode { .. }), "unexpected error {:?}", error);
+    let shrinking = shrinking.borrow();
+    assert_eq!(shrinking[..3], [(75.0, 75), (75.0, 60), (75.0, 45)]);
+    assert_eq!(shrinking.len(), 4);
+    assert!(shrinking[3].0 < 75.0);
+  }
+
+  #[test]
+  fn smaller_settings_lowers_quality_then_resolution() {
+    let settings = PageSettings::default();
+    let smaller = smaller_settings(&settings, 2_000_000, 1_000_000, true).unwrap();
+    assert_eq!(smaller.jpeg_quality, SHRINK_QUALITIES[0]);
+    assert_eq!(smaller.dpi(), settings.dpi());
+
+    let lowest = PageSettings { jpeg_quality: SHRINK_QUALITIES[2], ..settings };
+    let smaller = smaller_settings(&lowest, 2_000_000, 1_000_000, true).unwrap();
+    assert_eq!(smaller.jpeg_quality, SHRINK_QUALITIES[2]);
+    assert!(smaller.dpi() < settings.dpi());
+  }
+
+  #[test]
+  fn smaller_settings_skips_quality_without_jpeg_pages() {
+    let settings = PageSettings::default();
+    let smaller = smaller_settings(&settings, 2_000_000, 1_000_000, false).unwrap();
+    assert_eq!(smaller.jpeg_quality, settings.jpeg_quality);
+    assert!(smaller.dpi() < settings.dpi());
+  }
+
+  #[test]
+  fn smaller_settings_stops_at_minimum_resolution() {
+    let settings = PageSettings::default().with_dpi(MIN_SHRINK_DPI);
+    assert!(smaller_settings(&settings, 2_000_000, 1_000_000, false).is_none());
+  }
+}
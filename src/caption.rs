@@ -0,0 +1,306 @@
+use std::fmt;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{NaiveDate, NaiveDateTime};
+use printpdf::Mm;
+
+use crate::metadata::ImageAndMetadata;
+use crate::page::PageSettings;
+use crate::text::wrap_text;
+
+/// How `{date}` is written when the template gives no format.
+const DEFAULT_DATE_FORMAT: &str = "%-d %B %Y %H:%M";
+
+/// Extensions of the text files read for `{text}`, tried in order after the
+/// image's own name, e.g. `photo.jpg.txt`.
+const SIDECAR_EXTENSIONS: [&str; 2] = ["txt", "md"];
+
+/// Size of caption text, in points.
+pub(crate) const CAPTION_FONT_SIZE: f64 = 10.0;
+/// Height of the baseline of the last caption line above the bottom of the page.
+pub(crate) const CAPTION_BASELINE: Mm = Mm(8.0);
+/// Distance between the baselines of two caption lines.
+pub const CAPTION_LINE_HEIGHT: Mm = Mm(4.5);
+/// Least distance between a caption and the sides of the page.
+const CAPTION_INSET: Mm = Mm(20.0);
+/// Captions that run longer are cut short with an ellipsis.
+const MAX_CAPTION_LINES: usize = 4;
+
+/// A piece of image metadata a caption template can show.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Field {
+  /// The capture date, written with a strftime format.
+  Date(String),
+  /// The file name of the image, without its directory.
+  Filename,
+  /// The EXIF `ImageDescription`.
+  Description,
+  /// The contents of the sidecar text file next to the image.
+  Text,
+  /// The EXIF `Model` of the camera.
+  Camera
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+  Literal(String),
+  Field(Field)
+}
+
+/// A caption made from image metadata, e.g. `{date:%b %d %Y %H:%M} — {description}`.
+/// Fields are `date`, optionally with a format after a colon, `filename`,
+/// `description`, `text` and `camera`; `{{` and `}}` stand for braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptionTemplate {
+  source: String,
+  parts: Vec<Part>
+}
+
+impl CaptionTemplate {
+  /// The caption for `image`. Fields the image has no value for are left
+  /// empty, along with the separators around them at either end; `None` if
+  /// nothing is left.
+  pub fn render(&self, image: &ImageAndMetadata) -> Option<String> {
+    let mut caption = String::new();
+    for part in &self.parts {
+      match part {
+        Part::Literal(text) => caption.push_str(text),
+        Part::Field(field) => caption.push_str(&field_value(field, image).unwrap_or_default())
+      }
+    }
+    let caption = caption.trim_matches(|c: char| c.is_whitespace() || "—–-|,;:·".contains(c));
+    if caption.is_empty() { None } else { Some(caption.to_string()) }
+  }
+}
+
+fn field_value(field: &Field, image: &ImageAndMetadata) -> Option<String> {
+  match field {
+    Field::Date(format) => format_date(&image.date_created, format),
+    Field::Filename => Path::new(&image.path).file_name().map(|name| name.to_string_lossy().into_owned()),
+    Field::Description => image.description.clone(),
+    Field::Text => sidecar_text(Path::new(&image.path)),
+    Field::Camera => image.camera_model.clone()
+  }
+}
+
+/// Writes `date` with a strftime format. `None` if the format asks for
+/// something a date without a time zone does not have, such as `%z`;
+/// `to_string` would panic on those.
+fn format_date(date: &NaiveDateTime, format: &str) -> Option<String> {
+  let mut text = String::new();
+  write!(text, "{}", date.format(format)).ok()?;
+  Some(text)
+}
+
+/// The text of the first sidecar file of `path` that exists, on one line.
+fn sidecar_text(path: &Path) -> Option<String> {
+  SIDECAR_EXTENSIONS.iter()
+    .filter_map(|extension| fs::read_to_string(format!("{}.{}", path.display(), extension)).ok())
+    .map(|text| text.split_whitespace().collect::<Vec<&str>>().join(" "))
+    .find(|text| !text.is_empty())
+}
+
+/// Left edge and width of the captions on pages of `settings`: inside the
+/// margins, and no closer to the sides than [`CAPTION_INSET`].
+pub(crate) fn caption_column(settings: &PageSettings) -> (Mm, Mm) {
+  let left = settings.margins.left.0.max(CAPTION_INSET.0);
+  let right = settings.margins.right.0.max(CAPTION_INSET.0);
+  // Margins that leave no room still get a narrow column rather than none
+  (Mm(left), Mm((settings.width.0 - left - right).max(CAPTION_INSET.0)))
+}
+
+/// Breaks `caption` into lines that fit `width` when printed, at spaces where
+/// possible, cutting it short with an ellipsis after [`MAX_CAPTION_LINES`].
+pub(crate) fn wrap_caption(caption: &str, width: Mm) -> Vec<String> {
+  wrap_text(caption, width, CAPTION_FONT_SIZE, MAX_CAPTION_LINES)
+}
+
+/// The image `path` holds the caption text for, if it is named like a sidecar
+/// file; whether that image exists is up to the caller.
+pub(crate) fn sidecar_image(path: &str) -> Option<&str> {
+  SIDECAR_EXTENSIONS.iter().find_map(|extension| path.strip_suffix(extension)?.strip_suffix('.'))
+}
+
+impl fmt::Display for CaptionTemplate {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.source)
+  }
+}
+
+impl FromStr for CaptionTemplate {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<CaptionTemplate, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+      match c {
+        '{' if chars.as_str().starts_with('{') => {
+          chars.next();
+          literal.push('{');
+        },
+        '}' if chars.as_str().starts_with('}') => {
+          chars.next();
+          literal.push('}');
+        },
+        '{' => {
+          let rest = chars.as_str();
+          let end = rest.find('}').ok_or_else(|| format!("unclosed {{ in caption template {:?}", s))?;
+          let field = parse_field(&rest[..end])?;
+          chars = rest[end + 1..].chars();
+          if !literal.is_empty() {
+            parts.push(Part::Literal(std::mem::take(&mut literal)));
+          }
+          parts.push(Part::Field(field));
+        },
+        '}' => return Err(format!("unmatched }} in caption template {:?}, write }}}} for a brace", s)),
+        _ => literal.push(c)
+      }
+    }
+    if !literal.is_empty() {
+      parts.push(Part::Literal(literal));
+    }
+    Ok(CaptionTemplate { source: s.to_string(), parts })
+  }
+}
+
+/// Parses what is between the braces of a template field.
+fn parse_field(s: &str) -> Result<Field, String> {
+  let (name, format) = match s.split_once(':') {
+    Some((name, format)) => (name.trim(), Some(format)),
+    None => (s.trim(), None)
+  };
+  match (name, format) {
+    ("date", None) => Ok(Field::Date(DEFAULT_DATE_FORMAT.to_string())),
+    ("date", Some(format)) => {
+      if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err(format!("invalid date format {:?} in caption template", format));
+      }
+      // Capture dates are shown as the camera's clock read them, without a time zone
+      let sample = NaiveDate::from_ymd(2000, 1, 1).and_hms(0, 0, 0);
+      if format_date(&sample, format).is_none() {
+        return Err(format!("date format {:?} in caption template needs a time zone, which capture dates do not have", format));
+      }
+      Ok(Field::Date(format.to_string()))
+    },
+    ("filename", None) => Ok(Field::Filename),
+    ("description", None) => Ok(Field::Description),
+    ("text", None) => Ok(Field::Text),
+    ("camera", None) => Ok(Field::Camera),
+    (_, Some(_)) if ["filename", "description", "text", "camera"].contains(&name) => {
+      Err(format!("caption field {:?} takes no format", name))
+    },
+    _ => Err(format!("unknown caption field {:?}, expected date, filename, description, text or camera", name))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dates::DateSource;
+  use crate::test_support::TempDir;
+  use crate::text::text_width;
+
+  fn image(path: &str, description: Option<&str>) -> ImageAndMetadata {
+    ImageAndMetadata {
+      path: path.to_string(),
+      date_created: NaiveDate::from_ymd(2024, 3, 16).and_hms(9, 5, 0),
+      date_source: DateSource::ExifOriginal,
+      date_zoned: None,
+      camera_model: Some("Canon EOS 80D".to_string()),
+      description: description.map(String::from),
+      orientation: 1
+    }
+  }
+
+  fn render(template: &str, image: &ImageAndMetadata) -> Option<String> {
+    template.parse::<CaptionTemplate>().unwrap().render(image)
+  }
+
+  #[test]
+  fn renders_fields() {
+    let photo = image("photos/IMG_0001.jpg", Some("Opening form"));
+    assert_eq!(render("{date:%b %d %Y %H:%M} — {description}", &photo).as_deref(), Some("Mar 16 2024 09:05 — Opening form"));
+    assert_eq!(render("{date}", &photo).as_deref(), Some("16 March 2024 09:05"));
+    assert_eq!(render("{filename} ({camera})", &photo).as_deref(), Some("IMG_0001.jpg (Canon EOS 80D)"));
+    assert_eq!(render("{{{filename}}}", &photo).as_deref(), Some("{IMG_0001.jpg}"));
+  }
+
+  #[test]
+  fn missing_fields_drop_their_separators() {
+    let photo = image("IMG_0001.jpg", None);
+    assert_eq!(render("{date:%Y} — {description}", &photo).as_deref(), Some("2024"));
+    assert_eq!(render("{description} | {date:%Y}", &photo).as_deref(), Some("2024"));
+    assert_eq!(render("{description}", &photo), None);
+  }
+
+  #[test]
+  fn reads_sidecar_text() {
+    let dir = TempDir::new();
+    let path = dir.join("photo.jpg");
+    let sidecar = format!("{}.md", path.display());
+    fs::write(&sidecar, "  First line\n\nsecond   line \n").unwrap();
+    let text = render("{text}", &image(&path.display().to_string(), None));
+    assert_eq!(text.as_deref(), Some("First line second line"));
+    assert_eq!(sidecar_image(&sidecar), Some(path.to_str().unwrap()));
+    assert_eq!(sidecar_image("notes.txt.jpg"), None);
+  }
+
+  #[test]
+  fn rejects_invalid_templates() {
+    for template in ["{date", "date}", "{size}", "{filename:%Y}", "{date:%Q}", "{date:%z}", "{date:%:z}", "{date:%Z}", "{date:%+}"] {
+      assert!(template.parse::<CaptionTemplate>().is_err(), "{:?} parsed", template);
+    }
+  }
+
+  #[test]
+  fn short_captions_stay_on_one_line() {
+    assert_eq!(wrap_caption("Opening  form\n", Mm(170.0)), vec!["Opening form"]);
+    assert!(wrap_caption("", Mm(170.0)).is_empty());
+  }
+
+  #[test]
+  fn long_captions_wrap_to_the_width() {
+    let caption = "A very long caption that goes on and on ".repeat(3);
+    let lines = wrap_caption(&caption, Mm(60.0));
+    assert!(lines.len() > 1 && lines.len() <= MAX_CAPTION_LINES);
+    assert!(lines.iter().all(|line| text_width(line, CAPTION_FONT_SIZE) <= 60.0));
+    assert_eq!(lines.join(" "), caption.trim_end());
+  }
+
+  #[test]
+  fn overlong_captions_end_in_an_ellipsis() {
+    let lines = wrap_caption(&"word ".repeat(200), Mm(60.0));
+    assert_eq!(lines.len(), MAX_CAPTION_LINES);
+    assert!(lines[MAX_CAPTION_LINES - 1].ends_with('…'));
+    assert!(lines.iter().all(|line| text_width(line, CAPTION_FONT_SIZE) <= 60.0));
+  }
+
+  #[test]
+  fn long_words_are_broken() {
+    let lines = wrap_caption(&"x".repeat(40), Mm(30.0));
+    assert!(lines.len() > 1 && lines.len() <= MAX_CAPTION_LINES);
+    assert!(lines.iter().all(|line| text_width(line, CAPTION_FONT_SIZE) <= 30.0));
+    assert_eq!(lines.concat(), "x".repeat(40));
+  }
+
+  #[test]
+  fn caption_space_grows_with_the_lines() {
+    let settings = PageSettings::default();
+    assert_eq!(settings.with_caption_space("Short").margins.bottom, crate::page::CAPTION_SPACE);
+    let long = settings.with_caption_space(&"A very long caption that goes on and on ".repeat(6));
+    assert!(long.margins.bottom.0 > crate::page::CAPTION_SPACE.0);
+    assert!(long.raster_height < settings.with_caption_space("Short").raster_height);
+  }
+
+  #[test]
+  fn display_shows_the_source() {
+    let template: CaptionTemplate = "{date} — {{x}}".parse().unwrap();
+    assert_eq!(template.to_string(), "{date} — {{x}}");
+  }
+}
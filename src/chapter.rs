@@ -0,0 +1,149 @@
+use chrono::{Duration, NaiveDateTime};
+
+use crate::dates::{gap_between, CaptureTime};
+use crate::pdf::{BookPage, PageContent};
+
+/// A run of photos taken close together, introduced by a divider page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+  /// Position of the chapter in the book, from 1.
+  pub number: usize,
+  /// Capture date of the chapter's earliest photo.
+  pub start: NaiveDateTime,
+  /// Capture date of the chapter's latest photo.
+  pub end: NaiveDateTime
+}
+
+impl Chapter {
+  pub fn title(&self) -> String {
+    format!("Chapter {}", self.number)
+  }
+
+  /// When the chapter's photos were taken, e.g. `Saturday, 16 March 2024, 09:10–11:45`.
+  pub fn date_range(&self) -> String {
+    let (start, end) = (self.start, self.end);
+    if start.date() != end.date() {
+      format!("{} – {}", start.format("%-d %B %Y, %H:%M"), end.format("%-d %B %Y, %H:%M"))
+    } else if start.format("%H:%M").to_string() == end.format("%H:%M").to_string() {
+      start.format("%A, %-d %B %Y, %H:%M").to_string()
+    } else {
+      format!("{}–{}", start.format("%A, %-d %B %Y, %H:%M"), end.format("%H:%M"))
+    }
+  }
+}
+
+/// Splits the book into chapters wherever consecutive photos were taken more
+/// than `gap` apart, time zones included, and puts a divider page before each. Pages without a capture
+/// date stay in the chapter before them. A book that makes only one chapter
+/// is returned unchanged.
+pub fn add_chapters(pages: Vec<BookPage>, gap: Duration) -> Vec<BookPage> {
+  // The page each chapter starts at, with its earliest and latest capture date
+  let mut chapters: Vec<(usize, NaiveDateTime, NaiveDateTime)> = Vec::new();
+  let mut previous: Option<CaptureTime> = None;
+  for (index, date) in pages.iter().enumerate().filter_map(|(i, page)| page.date.map(|d| (i, d))) {
+    let starts_chapter = previous.is_none_or(|p| gap_between(&p, &date) > gap);
+    match chapters.last_mut() {
+      Some(chapter) if !starts_chapter => {
+        chapter.1 = chapter.1.min(date.local);
+        chapter.2 = chapter.2.max(date.local);
+      },
+      // Undated pages at the very start belong to the first chapter
+      _ => chapters.push((if chapters.is_empty() { 0 } else { index }, date.local, date.local))
+    }
+    previous = Some(date);
+  }
+  if chapters.len() < 2 {
+    return pages;
+  }
+
+  let mut book = Vec::with_capacity(pages.len() + chapters.len());
+  let mut chapters = chapters.into_iter().enumerate().peekable();
+  for (index, page) in pages.into_iter().enumerate() {
+    if let Some((n, (_, start, end))) = chapters.next_if(|(_, (first, ..))| *first == index) {
+      let chapter = Chapter { number: n + 1, start, end };
+      book.push(BookPage { content: PageContent::Divider(chapter), section: None, caption: None, sideways: false, date: None });
+    }
+    book.push(page);
+  }
+  book
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_support::{at, page, zoned_page};
+
+  /// The chapter of each divider and, for every other page, its date.
+  fn layout(book: &[BookPage]) -> Vec<Result<Chapter, Option<NaiveDateTime>>> {
+    book.iter().map(|page| match &page.content {
+      PageContent::Divider(chapter) => Ok(chapter.clone()),
+      _ => Err(page.date.map(|date| date.local))
+    }).collect()
+  }
+
+  #[test]
+  fn splits_at_gaps() {
+    let pages = vec![page(Some(at(16, 9, 0))), page(Some(at(16, 9, 30))), page(Some(at(16, 14, 0)))];
+    assert_eq!(layout(&add_chapters(pages, Duration::hours(1))), vec![
+      Ok(Chapter { number: 1, start: at(16, 9, 0), end: at(16, 9, 30) }),
+      Err(Some(at(16, 9, 0))),
+      Err(Some(at(16, 9, 30))),
+      Ok(Chapter { number: 2, start: at(16, 14, 0), end: at(16, 14, 0) }),
+      Err(Some(at(16, 14, 0)))
+    ]);
+  }
+
+  #[test]
+  fn splits_reversed_books() {
+    let pages = vec![page(Some(at(17, 10, 0))), page(Some(at(16, 9, 30))), page(Some(at(16, 9, 0)))];
+    assert_eq!(layout(&add_chapters(pages, Duration::hours(1))), vec![
+      Ok(Chapter { number: 1, start: at(17, 10, 0), end: at(17, 10, 0) }),
+      Err(Some(at(17, 10, 0))),
+      Ok(Chapter { number: 2, start: at(16, 9, 0), end: at(16, 9, 30) }),
+      Err(Some(at(16, 9, 30))),
+      Err(Some(at(16, 9, 0)))
+    ]);
+  }
+
+  #[test]
+  fn gaps_are_measured_across_time_zones() {
+    // Ten minutes apart, but five hours apart on the cameras' clocks
+    let pages = vec![zoned_page(at(16, 9, 0), 0), zoned_page(at(16, 14, 10), 5), zoned_page(at(16, 9, 20), 5)];
+    assert_eq!(layout(&add_chapters(pages, Duration::hours(1))), vec![
+      Ok(Chapter { number: 1, start: at(16, 9, 0), end: at(16, 14, 10) }),
+      Err(Some(at(16, 9, 0))),
+      Err(Some(at(16, 14, 10))),
+      Ok(Chapter { number: 2, start: at(16, 9, 20), end: at(16, 9, 20) }),
+      Err(Some(at(16, 9, 20)))
+    ]);
+  }
+
+  #[test]
+  fn undated_pages_stay_with_the_chapter_before() {
+    let pages = vec![page(None), page(Some(at(16, 9, 0))), page(None), page(Some(at(16, 14, 0))), page(None)];
+    assert_eq!(layout(&add_chapters(pages, Duration::hours(1))), vec![
+      Ok(Chapter { number: 1, start: at(16, 9, 0), end: at(16, 9, 0) }),
+      Err(None),
+      Err(Some(at(16, 9, 0))),
+      Err(None),
+      Ok(Chapter { number: 2, start: at(16, 14, 0), end: at(16, 14, 0) }),
+      Err(Some(at(16, 14, 0))),
+      Err(None)
+    ]);
+  }
+
+  #[test]
+  fn a_single_chapter_adds_no_divider() {
+    let pages = vec![page(Some(at(16, 9, 0))), page(None), page(Some(at(16, 9, 45)))];
+    assert_eq!(add_chapters(pages, Duration::hours(1)).len(), 3);
+    assert!(add_chapters(Vec::new(), Duration::hours(1)).is_empty());
+  }
+
+  #[test]
+  fn date_range_is_brief() {
+    let chapter = |start, end| Chapter { number: 1, start, end };
+    assert_eq!(chapter(at(16, 9, 10), at(16, 11, 45)).date_range(), "Saturday, 16 March 2024, 09:10–11:45");
+    assert_eq!(chapter(at(16, 9, 10), at(16, 9, 10)).date_range(), "Saturday, 16 March 2024, 09:10");
+    assert_eq!(chapter(at(16, 9, 10), at(17, 8, 0)).date_range(), "16 March 2024, 09:10 – 17 March 2024, 08:00");
+  }
+}
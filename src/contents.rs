@@ -0,0 +1,155 @@
+use printpdf::Mm;
+
+use crate::outline::{build_outline, OutlineGrouping};
+use crate::page::PageSettings;
+use crate::pdf::{BookPage, PageContent};
+use crate::text::{text_width, wrap_text};
+
+/// Height of the area at the top of a contents page that holds the heading.
+const HEADING_SPACE: Mm = Mm(45.0);
+/// Space kept free at the bottom of a contents page.
+const BOTTOM_SPACE: Mm = Mm(20.0);
+/// Distance between the baselines of two entries.
+const LINE_HEIGHT: Mm = Mm(8.0);
+/// Distance of the entries from either side of the page.
+pub(crate) const SIDE_SPACE: Mm = Mm(20.0);
+/// Least space between the end of a title and its page number.
+const NUMBER_GAP: Mm = Mm(5.0);
+/// Size of the entry text, in points.
+pub(crate) const ENTRY_FONT_SIZE: f64 = 12.0;
+
+/// A line of the table of contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentsEntry {
+  pub title: String,
+  /// Index of the page the entry links to, from 0.
+  pub page: usize
+}
+
+/// How many entries fit on one contents page.
+fn entries_per_page(settings: &PageSettings) -> usize {
+  (((settings.height.0 - HEADING_SPACE.0 - BOTTOM_SPACE.0) / LINE_HEIGHT.0) as usize + 1).max(1)
+}
+
+/// Height of the baseline of the `line`th entry on a contents page.
+pub(crate) fn entry_baseline(settings: &PageSettings, line: usize) -> Mm {
+  Mm(settings.height.0 - HEADING_SPACE.0 - line as f64 * LINE_HEIGHT.0)
+}
+
+/// The title of `entry` as printed, cut short with an ellipsis where it would
+/// run into the page number, and the page number with its left edge.
+pub(crate) fn entry_text(entry: &ContentsEntry, settings: &PageSettings) -> (String, String, Mm) {
+  let number = (entry.page + 1).to_string();
+  let number_left = settings.width.0 - SIDE_SPACE.0 - text_width(&number, ENTRY_FONT_SIZE);
+  let title_width = Mm(number_left - NUMBER_GAP.0 - SIDE_SPACE.0);
+  let title = wrap_text(&entry.title, title_width, ENTRY_FONT_SIZE, 1).pop().unwrap_or_default();
+  (title, number, Mm(number_left))
+}
+
+/// Inserts table of contents pages at `position`, listing the chapters, or
+/// the top level date bookmarks in a book without chapters, and the manifest
+/// sections. A book with nothing to list is returned unchanged.
+pub fn add_contents(mut pages: Vec<BookPage>, position: usize, grouping: OutlineGrouping, settings: &PageSettings) -> Vec<BookPage> {
+  let entries: Vec<ContentsEntry> = build_outline(&pages, grouping).into_iter()
+    .map(|entry| ContentsEntry { title: entry.title, page: entry.page })
+    .collect();
+  if entries.is_empty() {
+    return pages;
+  }
+
+  let per_page = entries_per_page(settings);
+  let contents_pages = entries.len().div_ceil(per_page);
+  // Every entry points past the contents pages once they are in place
+  let entries: Vec<ContentsEntry> = entries.into_iter()
+    .map(|entry| ContentsEntry { page: if entry.page >= position { entry.page + contents_pages } else { entry.page }, ..entry })
+    .collect();
+
+  let contents = entries.chunks(per_page).enumerate().map(|(n, chunk)| BookPage {
+    content: PageContent::Contents { entries: chunk.to_vec(), continued: n > 0 },
+    section: None,
+    caption: None,
+    sideways: false,
+    date: None
+  });
+  pages.splice(position..position, contents);
+  pages
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::{Duration, NaiveDate};
+
+  use crate::test_support::{at, page};
+
+  /// The entries of each contents page, as titles with page indexes.
+  fn contents(book: &[BookPage]) -> Vec<(Vec<(String, usize)>, bool)> {
+    book.iter().filter_map(|page| match &page.content {
+      PageContent::Contents { entries, continued } => {
+        Some((entries.iter().map(|entry| (entry.title.clone(), entry.page)).collect(), *continued))
+      },
+      _ => None
+    }).collect()
+  }
+
+  #[test]
+  fn entries_point_past_the_contents() {
+    let day = |d| Some(at(d, 10, 0));
+    // A cover, then the photos of two days
+    let pages = vec![page(None), page(day(16)), page(day(16)), page(day(17))];
+    let book = add_contents(pages, 1, OutlineGrouping::Day, &PageSettings::default());
+    assert_eq!(book.len(), 5);
+    assert_eq!(contents(&book), vec![
+      (vec![("Saturday, 16 March 2024".to_string(), 2), ("Sunday, 17 March 2024".to_string(), 4)], false)
+    ]);
+    assert!(matches!(book[1].content, PageContent::Contents { .. }));
+    assert_eq!(book[2].date.map(|date| date.local), day(16));
+  }
+
+  #[test]
+  fn sections_before_the_contents_keep_their_page() {
+    let mut pages = vec![page(None), page(None)];
+    pages[0].section = Some("Title".to_string());
+    pages[1].section = Some("Photos".to_string());
+    let book = add_contents(pages, 1, OutlineGrouping::None, &PageSettings::default());
+    assert_eq!(contents(&book), vec![
+      (vec![("Title".to_string(), 0), ("Photos".to_string(), 2)], false)
+    ]);
+  }
+
+  #[test]
+  fn long_contents_continue_on_further_pages() {
+    let settings = PageSettings::default();
+    let per_page = entries_per_page(&settings);
+    let start = NaiveDate::from_ymd(2024, 1, 1).and_hms(10, 0, 0);
+    let pages: Vec<BookPage> = (0..per_page as i64 + 1).map(|n| page(Some(start + Duration::days(n)))).collect();
+    let book = add_contents(pages, 0, OutlineGrouping::Day, &settings);
+    let contents = contents(&book);
+    assert_eq!(contents.len(), 2);
+    assert_eq!((contents[0].0.len(), contents[0].1), (per_page, false));
+    assert_eq!((contents[1].0.len(), contents[1].1), (1, true));
+    // Both contents pages come first, so the first day is on the third page
+    assert_eq!(contents[0].0[0].1, 2);
+    assert_eq!(contents[1].0[0].1, per_page + 2);
+  }
+
+  #[test]
+  fn long_titles_stop_before_the_page_number() {
+    let settings = PageSettings::default();
+    let entry = ContentsEntry { title: "Chapter 12: Saturday, 16 March 2024 to Sunday, 17 March 2024, with the excursion to the coast and the long walk back".to_string(), page: 119 };
+    let (title, number, number_left) = entry_text(&entry, &settings);
+    assert_eq!(number, "120");
+    assert!(title.ends_with('…'));
+    assert!(SIDE_SPACE.0 + text_width(&title, ENTRY_FONT_SIZE) + NUMBER_GAP.0 <= number_left.0);
+    assert!((number_left.0 + text_width(&number, ENTRY_FONT_SIZE) - (settings.width.0 - SIDE_SPACE.0)).abs() < 1e-9);
+
+    let entry = ContentsEntry { title: "Saturday, 16 March 2024".to_string(), page: 3 };
+    assert_eq!(entry_text(&entry, &settings).0, "Saturday, 16 March 2024");
+  }
+
+  #[test]
+  fn nothing_to_list_adds_no_page() {
+    let pages = vec![page(None), page(None)];
+    assert_eq!(add_contents(pages, 0, OutlineGrouping::Day, &PageSettings::default()).len(), 2);
+  }
+}
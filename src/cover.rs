@@ -0,0 +1,147 @@
+use std::path::PathBuf;
+
+use chrono::{Datelike, NaiveDate};
+use printpdf::Mm;
+
+use crate::encode::EncodedImage;
+use crate::page::{FitMode, LandscapePolicy, Margins, PageSettings};
+use crate::text::{wrap_text, BOLD_WIDTH};
+
+/// What the generated cover page shows besides the title and author.
+#[derive(Debug, Clone, Default)]
+pub struct CoverOptions {
+  /// A line under the title.
+  pub subtitle: Option<String>,
+  /// A photo shown large in the middle of the cover.
+  pub image: Option<PathBuf>
+}
+
+/// The content of the cover page.
+#[derive(Debug, Clone)]
+pub struct Cover {
+  pub title: String,
+  pub subtitle: Option<String>,
+  pub author: Option<String>,
+  /// The days the photos were taken on, see [`date_span`].
+  pub dates: Option<String>,
+  pub image: Option<EncodedImage>
+}
+
+/// Space kept free above the cover image for the title and subtitle.
+const COVER_TOP: Mm = Mm(70.0);
+/// Space kept free below the cover image for the author and dates.
+const COVER_BOTTOM: Mm = Mm(60.0);
+/// Space kept free left and right of the cover image, and of the text.
+pub(crate) const COVER_SIDE: Mm = Mm(20.0);
+/// Size of the title, in points; it is set in bold.
+pub(crate) const TITLE_FONT_SIZE: f64 = 28.0;
+/// Size of the subtitle, in points.
+pub(crate) const SUBTITLE_FONT_SIZE: f64 = 16.0;
+/// Distance of the first title baseline from the top of the page.
+const TITLE_BASELINE: Mm = Mm(30.0);
+/// Distance between the baselines of two title lines.
+const TITLE_LINE_HEIGHT: Mm = Mm(11.0);
+/// Distance between the baselines of the last title line and the subtitle.
+const SUBTITLE_SPACE: Mm = Mm(13.0);
+/// Titles that run longer are cut short with an ellipsis, so that they stay
+/// above the cover image.
+const MAX_TITLE_LINES: usize = 3;
+
+/// The settings the cover image is rendered and placed with: the area between
+/// the title and the author, the whole photo shown upright.
+pub fn cover_image_settings(settings: &PageSettings) -> PageSettings {
+  let mut cover = settings.with_margins(Margins { top: COVER_TOP, right: COVER_SIDE, bottom: COVER_BOTTOM, left: COVER_SIDE });
+  cover.fit = FitMode::Contain;
+  cover.landscape = LandscapePolicy::FitPortrait;
+  cover
+}
+
+/// A line of cover text and the height of its baseline.
+pub(crate) type CoverLine = (String, Mm);
+
+/// The title of `cover` broken into lines that fit between the sides of the
+/// page, and the subtitle cut short to one line, each with the height of its
+/// baseline.
+pub(crate) fn cover_heading(cover: &Cover, settings: &PageSettings) -> (Vec<CoverLine>, Option<CoverLine>) {
+  let width = Mm(settings.width.0 - 2.0 * COVER_SIDE.0);
+  let top = settings.height.0 - TITLE_BASELINE.0;
+  let title: Vec<CoverLine> = wrap_text(&cover.title, width, TITLE_FONT_SIZE * BOLD_WIDTH, MAX_TITLE_LINES).into_iter()
+    .enumerate()
+    .map(|(n, line)| (line, Mm(top - n as f64 * TITLE_LINE_HEIGHT.0)))
+    .collect();
+  let subtitle_baseline = top - (title.len().max(1) - 1) as f64 * TITLE_LINE_HEIGHT.0 - SUBTITLE_SPACE.0;
+  let subtitle = cover.subtitle.as_ref()
+    .and_then(|subtitle| wrap_text(subtitle, width, SUBTITLE_FONT_SIZE, 1).pop())
+    .map(|line| (line, Mm(subtitle_baseline)));
+  (title, subtitle)
+}
+
+/// The days from `start` to `end`, written as briefly as possible, e.g.
+/// `15–17 March 2024` or `30 March – 2 April 2024`.
+pub fn date_span(start: NaiveDate, end: NaiveDate) -> String {
+  if start == end {
+    start.format("%A, %-d %B %Y").to_string()
+  } else if (start.year(), start.month()) == (end.year(), end.month()) {
+    format!("{}–{}", start.format("%-d"), end.format("%-d %B %Y"))
+  } else if start.year() == end.year() {
+    format!("{} – {}", start.format("%-d %B"), end.format("%-d %B %Y"))
+  } else {
+    format!("{} – {}", start.format("%-d %B %Y"), end.format("%-d %B %Y"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::text::text_width;
+
+  fn cover(title: &str, subtitle: Option<&str>) -> Cover {
+    Cover { title: title.to_string(), subtitle: subtitle.map(str::to_string), author: None, dates: None, image: None }
+  }
+
+  #[test]
+  fn date_spans_are_brief() {
+    let day = |y, m, d| NaiveDate::from_ymd(y, m, d);
+    assert_eq!(date_span(day(2024, 3, 16), day(2024, 3, 16)), "Saturday, 16 March 2024");
+    assert_eq!(date_span(day(2024, 3, 15), day(2024, 3, 17)), "15–17 March 2024");
+    assert_eq!(date_span(day(2024, 3, 30), day(2024, 4, 2)), "30 March – 2 April 2024");
+    assert_eq!(date_span(day(2023, 12, 30), day(2024, 1, 2)), "30 December 2023 – 2 January 2024");
+  }
+
+  #[test]
+  fn cover_images_fit_between_title_and_author() {
+    let settings = PageSettings { fit: FitMode::Cover, landscape: LandscapePolicy::LandscapePage, ..PageSettings::default() };
+    let cover = cover_image_settings(&settings);
+    assert_eq!(cover.margins, Margins { top: COVER_TOP, right: COVER_SIDE, bottom: COVER_BOTTOM, left: COVER_SIDE });
+    assert_eq!(cover.fit, FitMode::Contain);
+    assert_eq!(cover.landscape, LandscapePolicy::FitPortrait);
+    assert_eq!((cover.width, cover.height), (settings.width, settings.height));
+  }
+
+  #[test]
+  fn short_titles_take_one_line() {
+    let settings = PageSettings::default();
+    let (title, subtitle) = cover_heading(&cover("Seminar", Some("Spring 2024")), &settings);
+    let top = settings.height.0 - TITLE_BASELINE.0;
+    assert_eq!(title, vec![("Seminar".to_string(), Mm(top))]);
+    assert_eq!(subtitle, Some(("Spring 2024".to_string(), Mm(top - SUBTITLE_SPACE.0))));
+  }
+
+  #[test]
+  fn long_titles_wrap_and_push_the_subtitle_down() {
+    let settings = PageSettings::default();
+    let width = settings.width.0 - 2.0 * COVER_SIDE.0;
+    let long = "The Annual Seminar of the Regional Karate Federation in the Mountains";
+    let (title, subtitle) = cover_heading(&cover(long, Some("Spring 2024")), &settings);
+    assert!(title.len() > 1 && title.len() <= MAX_TITLE_LINES);
+    assert!(title.iter().all(|(line, _)| text_width(line, TITLE_FONT_SIZE * BOLD_WIDTH) <= width));
+    let last = title.last().unwrap().1;
+    assert_eq!(subtitle.unwrap().1, Mm(last.0 - SUBTITLE_SPACE.0));
+    // Everything stays above the cover image
+    assert!(last.0 - SUBTITLE_SPACE.0 > settings.height.0 - COVER_TOP.0);
+
+    let (title, _) = cover_heading(&cover(&long.repeat(4), None), &settings);
+    assert_eq!(title.len(), MAX_TITLE_LINES);
+    assert!(title[MAX_TITLE_LINES - 1].0.ends_with('…'));
+  }
+}
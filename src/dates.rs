@@ -0,0 +1,399 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+/// A place the capture date of an image can be taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateSource {
+  /// EXIF `DateTimeOriginal`, when the shutter was pressed.
+  ExifOriginal,
+  /// EXIF `DateTimeDigitized`, when the image was stored.
+  ExifDigitized,
+  /// EXIF `DateTime`, when the file was last changed by the camera or an editor.
+  ExifDateTime,
+  /// A PNG `tIME` chunk or a `Creation Time` text chunk.
+  Png,
+  /// A date embedded in the file name, such as `IMG_20210314_101500.jpg`.
+  Filename,
+  /// The modification time recorded by the filesystem.
+  Mtime
+}
+
+impl DateSource {
+  /// The order used when no other is configured: most to least trustworthy.
+  pub const DEFAULT_CHAIN: [DateSource; 6] = [
+    DateSource::ExifOriginal,
+    DateSource::ExifDigitized,
+    DateSource::ExifDateTime,
+    DateSource::Png,
+    DateSource::Filename,
+    DateSource::Mtime
+  ];
+
+  /// The EXIF tag this source reads, if it is an EXIF source.
+  pub fn exif_tag(self) -> Option<exif::Tag> {
+    match self {
+      DateSource::ExifOriginal => Some(exif::Tag::DateTimeOriginal),
+      DateSource::ExifDigitized => Some(exif::Tag::DateTimeDigitized),
+      DateSource::ExifDateTime => Some(exif::Tag::DateTime),
+      _ => None
+    }
+  }
+
+  fn name(self) -> &'static str {
+    match self {
+      DateSource::ExifOriginal => "exif-original",
+      DateSource::ExifDigitized => "exif-digitized",
+      DateSource::ExifDateTime => "exif-datetime",
+      DateSource::Png => "png",
+      DateSource::Filename => "filename",
+      DateSource::Mtime => "mtime"
+    }
+  }
+}
+
+impl fmt::Display for DateSource {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+impl FromStr for DateSource {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<DateSource, String> {
+    DateSource::DEFAULT_CHAIN.iter()
+      .copied()
+      .find(|source| source.name() == s)
+      .ok_or_else(|| format!("unknown date source {:?}", s))
+  }
+}
+
+/// Largest `tIME` or `tEXt` chunk read for a date; anything bigger is not a
+/// date, and the length of a corrupt chunk may be anything up to 4 GiB.
+const MAX_DATE_CHUNK: usize = 64 * 1024;
+
+/// Reads the time stored in a PNG `tIME` chunk, or failing that a `Creation Time`
+/// `tEXt` chunk. Returns `None` for anything that is not a PNG.
+pub fn png_date(path: &Path) -> Option<NaiveDateTime> {
+  const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+  let mut reader = BufReader::new(File::open(path).ok()?);
+  let mut signature = [0u8; 8];
+  reader.read_exact(&mut signature).ok()?;
+  if signature != SIGNATURE {
+    return None;
+  }
+
+  let mut creation_time = None;
+  loop {
+    let mut header = [0u8; 8];
+    if reader.read_exact(&mut header).is_err() {
+      break;
+    }
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let chunk_type = &header[4..8];
+
+    match chunk_type {
+      b"tIME" | b"tEXt" if length <= MAX_DATE_CHUNK => {
+        let mut data = vec![0u8; length];
+        reader.read_exact(&mut data).ok()?;
+        reader.seek(SeekFrom::Current(4)).ok()?;
+
+        if chunk_type == b"tIME" {
+          if let Some(time) = parse_png_time(&data) {
+            return Some(time);
+          }
+        } else if creation_time.is_none() {
+          creation_time = parse_png_creation_time(&data);
+        }
+      },
+      b"IEND" => break,
+      // Skip the chunk data and its CRC, including oversized date chunks
+      _ => { reader.seek(SeekFrom::Current(length as i64 + 4)).ok()?; }
+    }
+  }
+
+  creation_time
+}
+
+fn parse_png_time(data: &[u8]) -> Option<NaiveDateTime> {
+  if data.len() != 7 {
+    return None;
+  }
+  let year = u16::from_be_bytes([data[0], data[1]]) as i32;
+  NaiveDate::from_ymd_opt(year, data[2] as u32, data[3] as u32)?
+    .and_hms_opt(data[4] as u32, data[5] as u32, data[6] as u32)
+}
+
+fn parse_png_creation_time(data: &[u8]) -> Option<NaiveDateTime> {
+  let separator = data.iter().position(|&b| b == 0)?;
+  if &data[..separator] != b"Creation Time" {
+    return None;
+  }
+  // tEXt is Latin-1, but dates are plain ASCII
+  let text = String::from_utf8_lossy(&data[separator + 1..]);
+  let text = text.trim();
+
+  if let Ok(date) = DateTime::parse_from_rfc2822(text) {
+    return Some(date.naive_local());
+  }
+  if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+    return Some(date.naive_local());
+  }
+  ["%Y:%m:%d %H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"].iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+}
+
+/// Finds a date in the file name, e.g. `IMG_20210314_101500.jpg`,
+/// `PXL_20210314_101500123.jpg` or `2021-03-14 10.15.00.png`. A name that
+/// only carries a date is placed at midnight.
+pub fn filename_date(path: &Path) -> Option<NaiveDateTime> {
+  let stem = path.file_stem()?.to_string_lossy();
+  let groups: Vec<&str> = stem.split(|c: char| !c.is_ascii_digit())
+    .filter(|g| !g.is_empty())
+    .collect();
+
+  (0..groups.len()).find_map(|start| date_from_digit_groups(&groups[start..]))
+}
+
+fn date_from_digit_groups(groups: &[&str]) -> Option<NaiveDateTime> {
+  match groups {
+    // "YYYYMMDD", possibly running straight on into "HHMMSS"
+    [ymd, rest @ ..] if ymd.len() >= 8 => {
+      if ymd.len() >= 14 {
+        combine(&ymd[..8], &ymd[8..14])
+      } else {
+        combine(&ymd[..8], &time_from_digit_groups(rest))
+      }
+    },
+    [y, m, d, rest @ ..] if y.len() == 4 && m.len() == 2 && d.len() == 2 => {
+      combine(&format!("{}{}{}", y, m, d), &time_from_digit_groups(rest))
+    },
+    _ => None
+  }
+}
+
+/// Either "HHMMSS" or "HH", "MM", "SS"; midnight if neither follows the date.
+fn time_from_digit_groups(groups: &[&str]) -> String {
+  match groups {
+    [hms, ..] if hms.len() >= 6 => hms[..6].to_string(),
+    [h, m, s, ..] if h.len() == 2 && m.len() == 2 && s.len() == 2 => format!("{}{}{}", h, m, s),
+    _ => "000000".to_string()
+  }
+}
+
+/// Combines "YYYYMMDD" and "HHMMSS", rejecting years that are unlikely to be a
+/// capture date rather than some other number in the name.
+fn combine(date: &str, time: &str) -> Option<NaiveDateTime> {
+  let number = |s: &str| s.parse::<u32>().ok();
+  let year = number(&date[0..4])? as i32;
+  if !(1900..=2100).contains(&year) {
+    return None;
+  }
+  NaiveDate::from_ymd_opt(year, number(&date[4..6])?, number(&date[6..8])?)?
+    .and_hms_opt(number(&time[0..2])?, number(&time[2..4])?, number(&time[4..6])?)
+}
+
+/// The modification time of the file, in local time.
+pub fn mtime_date(path: &Path) -> Option<NaiveDateTime> {
+  let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+  Some(DateTime::<Local>::from(modified).naive_local())
+}
+
+/// When a photo was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureTime {
+  /// The time on the camera's clock, after clock corrections, as the book shows it.
+  pub local: NaiveDateTime,
+  /// The moment itself, with the camera's time zone applied, see
+  /// [`ImageAndMetadata::instant`](crate::ImageAndMetadata::instant).
+  pub instant: DateTime<Utc>
+}
+
+/// How far apart two photos were taken, whichever came first. Reversed books
+/// run backwards in time, so only the distance counts.
+pub(crate) fn gap_between(a: &CaptureTime, b: &CaptureTime) -> Duration {
+  if a.instant > b.instant { a.instant - b.instant } else { b.instant - a.instant }
+}
+
+/// Parses a date given on the command line: RFC 3339 with a time zone, or
+/// `YYYY-MM-DD[ HH:MM[:SS]]` in local time.
+pub fn parse_date_time(text: &str) -> Result<DateTime<FixedOffset>, String> {
+  let text = text.trim();
+  if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+    return Ok(date);
+  }
+  let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"].iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+    .or_else(|| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok().map(|date| date.and_hms(0, 0, 0)))
+    .ok_or_else(|| format!("invalid date {:?}, expected YYYY-MM-DD, YYYY-MM-DD HH:MM[:SS] or RFC 3339", text))?;
+  let local = Local.from_local_datetime(&naive).earliest()
+    .ok_or_else(|| format!("{:?} does not exist in the local time zone", text))?;
+  Ok(local.with_timezone(local.offset()))
+}
+
+/// Parses a length of time such as `45m`, `2h`, `1h30m` or `90s`.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+  let invalid = || format!("invalid duration {:?}, expected e.g. 45m, 2h or 1h30m", text);
+  let too_long = || format!("duration {:?} is too long", text);
+  let mut total = Duration::zero();
+  let mut rest = text.trim();
+  if rest.is_empty() {
+    return Err(invalid());
+  }
+  while !rest.is_empty() {
+    let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let number: i64 = rest[..digits].parse().map_err(|_| invalid())?;
+    let unit = rest[digits..].chars().next().ok_or_else(invalid)?;
+    let unit_seconds = match unit {
+      'd' => 86_400,
+      'h' => 3_600,
+      'm' => 60,
+      's' => 1,
+      _ => return Err(invalid())
+    };
+    // Duration keeps milliseconds in an i64, which bounds the seconds it can hold
+    total = number.checked_mul(unit_seconds)
+      .filter(|seconds| *seconds <= i64::MAX / 1000)
+      .and_then(|seconds| total.checked_add(&Duration::seconds(seconds)))
+      .ok_or_else(too_long)?;
+    rest = &rest[digits + unit.len_utf8()..];
+  }
+  Ok(total)
+}
+
+/// Largest clock correction accepted, in hours: a hundred years, which covers
+/// any camera whose clock was never set.
+const MAX_CLOCK_OFFSET_HOURS: i64 = 100 * 366 * 24;
+
+/// A correction for a camera whose clock is wrong, added to the capture date of
+/// every image whose EXIF `Model` matches `camera_model`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClockOffset {
+  pub camera_model: String,
+  pub offset: Duration
+}
+
+impl FromStr for ClockOffset {
+  type Err = String;
+
+  /// Parses `MODEL=[+|-]HH:MM[:SS]`, e.g. `Canon EOS 80D=+01:02:30`, with at
+  /// most a hundred years' worth of hours.
+  fn from_str(s: &str) -> Result<ClockOffset, String> {
+    let invalid = || format!("invalid clock offset {:?}, expected MODEL=[+|-]HH:MM[:SS]", s);
+
+    let separator = s.rfind('=').ok_or_else(invalid)?;
+    let camera_model = s[..separator].trim();
+    let value = s[separator + 1..].trim();
+    if camera_model.is_empty() {
+      return Err(invalid());
+    }
+
+    let (sign, value) = match value.as_bytes().first() {
+      Some(b'-') => (-1, &value[1..]),
+      Some(b'+') => (1, &value[1..]),
+      _ => (1, value)
+    };
+    let parts = value.split(':')
+      .map(|part| part.parse::<i64>().ok().filter(|n| *n >= 0))
+      .collect::<Option<Vec<i64>>>()
+      .ok_or_else(invalid)?;
+    let seconds = match parts.as_slice() {
+      [h, m] if *h <= MAX_CLOCK_OFFSET_HOURS && *m < 60 => h * 3600 + m * 60,
+      [h, m, sec] if *h <= MAX_CLOCK_OFFSET_HOURS && *m < 60 && *sec < 60 => h * 3600 + m * 60 + sec,
+      _ => return Err(invalid())
+    };
+
+    Ok(ClockOffset { camera_model: camera_model.to_string(), offset: Duration::seconds(sign * seconds) })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_support::TempDir;
+
+  fn date(y: i32, m: u32, d: u32, h: u32, min: u32, s: u32) -> NaiveDateTime {
+    NaiveDate::from_ymd(y, m, d).and_hms(h, min, s)
+  }
+
+  #[test]
+  fn filename_date_camera_names() {
+    assert_eq!(filename_date(Path::new("IMG_20210314_101500.jpg")), Some(date(2021, 3, 14, 10, 15, 0)));
+    assert_eq!(filename_date(Path::new("PXL_20210314_101500123.jpg")), Some(date(2021, 3, 14, 10, 15, 0)));
+    assert_eq!(filename_date(Path::new("20210314101500.jpg")), Some(date(2021, 3, 14, 10, 15, 0)));
+  }
+
+  #[test]
+  fn filename_date_separated_groups() {
+    assert_eq!(filename_date(Path::new("2021-03-14 10.15.00.png")), Some(date(2021, 3, 14, 10, 15, 0)));
+    assert_eq!(filename_date(Path::new("seminar 2021-03-14.jpg")), Some(date(2021, 3, 14, 0, 0, 0)));
+  }
+
+  #[test]
+  fn filename_date_rejects_other_numbers() {
+    assert_eq!(filename_date(Path::new("DSC_0042.jpg")), None);
+    assert_eq!(filename_date(Path::new("scan_12345678.png")), None);
+    assert_eq!(filename_date(Path::new("IMG_20211340_101500.jpg")), None);
+    // A year out of range is skipped in favour of a later date in the name
+    assert_eq!(filename_date(Path::new("30000101_2021-03-14.jpg")), Some(date(2021, 3, 14, 0, 0, 0)));
+  }
+
+  #[test]
+  fn parse_duration_adds_units() {
+    assert_eq!(parse_duration("45m"), Ok(Duration::minutes(45)));
+    assert_eq!(parse_duration("1h30m"), Ok(Duration::minutes(90)));
+    assert_eq!(parse_duration(" 2d "), Ok(Duration::days(2)));
+    assert_eq!(parse_duration("90s"), Ok(Duration::seconds(90)));
+  }
+
+  #[test]
+  fn parse_duration_rejects_invalid() {
+    for text in ["", "45", "m", "45x", "-45m", "1.5h", "45m30"] {
+      assert!(parse_duration(text).is_err(), "{:?} parsed", text);
+    }
+  }
+
+  #[test]
+  fn parse_duration_rejects_overflow() {
+    for text in ["99999999999999d", "9223372036854775807s", "99999999999999999999s", "9223372036854775s1s"] {
+      assert!(parse_duration(text).is_err(), "{:?} parsed", text);
+    }
+  }
+
+  #[test]
+  fn clock_offset_parses() {
+    let offset: ClockOffset = "Canon EOS 80D=+01:02:30".parse().unwrap();
+    assert_eq!(offset.camera_model, "Canon EOS 80D");
+    assert_eq!(offset.offset, Duration::seconds(3750));
+    assert_eq!("X=-00:30".parse::<ClockOffset>().unwrap().offset, Duration::minutes(-30));
+    // The model may itself contain an equals sign
+    assert_eq!("A=B=12:00".parse::<ClockOffset>().unwrap().camera_model, "A=B");
+  }
+
+  #[test]
+  fn clock_offset_rejects_invalid() {
+    for text in ["=+01:00", "X", "X=+01", "X=01:60", "X=01:00:60", "X=+-01:00", "X=+9999999999999999:00", "X=+9999999999:00"] {
+      assert!(text.parse::<ClockOffset>().is_err(), "{:?} parsed", text);
+    }
+    assert!(format!("X={}:00", MAX_CLOCK_OFFSET_HOURS).parse::<ClockOffset>().is_ok());
+  }
+
+  #[test]
+  fn png_date_skips_oversized_chunks() {
+    let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+    // A tEXt chunk claiming to be 4 GiB long, cut short
+    png.extend_from_slice(&u32::MAX.to_be_bytes());
+    png.extend_from_slice(b"tEXt");
+    png.extend_from_slice(b"Creation Time\0");
+    let dir = TempDir::new();
+    let path = dir.join("oversized.png");
+    std::fs::write(&path, &png).unwrap();
+    assert_eq!(png_date(&path), None);
+  }
+}
@@ -0,0 +1,228 @@
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::{ColorType, DynamicImage, GenericImageView, GrayImage};
+use printpdf::ColorSpace;
+
+use crate::error::{BookerError, Result};
+
+/// JPEG quality used when none is configured.
+pub const DEFAULT_JPEG_QUALITY: u8 = 85;
+
+/// How page rasters are compressed in the PDF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImageEncoding {
+  /// Lossy JPEG (DCT), small for photos.
+  Jpeg,
+  /// Lossless Flate, small and sharp for scanned text and line art.
+  Flate,
+  /// Flate for pages that look like scanned text, JPEG for everything else.
+  #[default]
+  Auto
+}
+
+impl ImageEncoding {
+  fn name(self) -> &'static str {
+    match self {
+      ImageEncoding::Jpeg => "jpeg",
+      ImageEncoding::Flate => "flate",
+      ImageEncoding::Auto => "auto"
+    }
+  }
+}
+
+impl fmt::Display for ImageEncoding {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+impl FromStr for ImageEncoding {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::result::Result<ImageEncoding, String> {
+    [ImageEncoding::Jpeg, ImageEncoding::Flate, ImageEncoding::Auto].iter()
+      .copied()
+      .find(|encoding| encoding.name() == s)
+      .ok_or_else(|| format!("unknown image encoding {:?}", s))
+  }
+}
+
+/// The PDF filter needed to decode the data of an [`EncodedImage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RasterFilter {
+  /// A complete JPEG file.
+  Dct,
+  /// Zlib compressed pixel rows.
+  Flate
+}
+
+/// A rendered page raster, compressed the way it is stored in the PDF.
+#[derive(Debug, Clone)]
+pub struct EncodedImage {
+  pub width: u32,
+  pub height: u32,
+  pub color_space: ColorSpace,
+  /// 1 for packed black and white pixels, otherwise 8.
+  pub bits_per_component: u8,
+  pub filter: RasterFilter,
+  pub data: Vec<u8>
+}
+
+impl EncodedImage {
+  /// Compresses a rendered page. `path` is the source image, for error messages.
+  pub fn encode(image: &DynamicImage, encoding: ImageEncoding, jpeg_quality: u8, path: &Path) -> Result<EncodedImage> {
+    let (image, color_space, color_type) = match image {
+      DynamicImage::ImageLuma8(_) => (image.clone(), ColorSpace::Greyscale, ColorType::L8),
+      _ => (DynamicImage::ImageRgb8(image.to_rgb8()), ColorSpace::Rgb, ColorType::Rgb8)
+    };
+    let (width, height) = image.dimensions();
+    let encode_error = |source| BookerError::Encode { path: path.to_path_buf(), source };
+
+    // Pure black and white pages, such as dithered ones, take one bit per pixel
+    let bilevel = match &image {
+      DynamicImage::ImageLuma8(gray) => gray.pixels().all(|p| p[0] == 0 || p[0] == 255),
+      _ => false
+    };
+    let use_flate = match encoding {
+      ImageEncoding::Jpeg => false,
+      ImageEncoding::Flate => true,
+      ImageEncoding::Auto => bilevel || looks_like_text(&image.to_luma8())
+    };
+    if !use_flate {
+      let mut data = Vec::new();
+      image::jpeg::JpegEncoder::new_with_quality(&mut data, jpeg_quality)
+        .encode(image.as_bytes(), width, height, color_type)
+        .map_err(encode_error)?;
+      return Ok(EncodedImage { width, height, color_space, bits_per_component: 8, filter: RasterFilter::Dct, data });
+    }
+
+    let (bits_per_component, pixels) = match &image {
+      DynamicImage::ImageLuma8(gray) if bilevel => (1, pack_bilevel(gray)),
+      _ => (8, image.as_bytes().to_vec())
+    };
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    let data = encoder.write_all(&pixels)
+      .and_then(|_| encoder.finish())
+      .map_err(|e| encode_error(image::ImageError::IoError(e)))?;
+    Ok(EncodedImage { width, height, color_space, bits_per_component, filter: RasterFilter::Flate, data })
+  }
+
+  /// Saves the page image as `<stem>.jpg`, or as `<stem>.png` if it is
+  /// stored losslessly, and returns the path written.
+  pub fn save(&self, stem: &Path) -> Result<PathBuf> {
+    if self.filter == RasterFilter::Dct {
+      let path = stem.with_extension("jpg");
+      fs::write(&path, &self.data).map_err(|e| BookerError::io(&path, e))?;
+      return Ok(path);
+    }
+
+    let path = stem.with_extension("png");
+    let mut pixels = Vec::new();
+    ZlibDecoder::new(&self.data[..]).read_to_end(&mut pixels).map_err(|e| BookerError::io(&path, e))?;
+    let color_type = match self.color_space {
+      ColorSpace::Greyscale => ColorType::L8,
+      _ => ColorType::Rgb8
+    };
+    if self.bits_per_component == 1 {
+      let row_bytes = (self.width as usize).div_ceil(8);
+      pixels = (0..self.height as usize)
+        .flat_map(|y| (0..self.width as usize).map(move |x| (y, x)))
+        .map(|(y, x)| if pixels[y * row_bytes + x / 8] & (0x80 >> (x % 8)) != 0 { 255 } else { 0 })
+        .collect();
+    }
+    image::save_buffer(&path, &pixels, self.width, self.height, color_type)
+      .map_err(|e| BookerError::Encode { path: path.clone(), source: e })?;
+    Ok(path)
+  }
+}
+
+/// Scanned text and line art is mostly paper with some ink, and hardly any
+/// of the mid tones that make up a photo.
+fn looks_like_text(gray: &GrayImage) -> bool {
+  let total = gray.pixels().len().max(1) as f64;
+  let paper = gray.pixels().filter(|p| p[0] > 192).count() as f64;
+  let ink = gray.pixels().filter(|p| p[0] < 64).count() as f64;
+  paper / total >= 0.5 && (paper + ink) / total >= 0.9
+}
+
+/// Packs a black and white image into rows of one bit per pixel, white as 1.
+fn pack_bilevel(gray: &GrayImage) -> Vec<u8> {
+  let row_bytes = (gray.width() as usize).div_ceil(8);
+  let mut packed = vec![0u8; row_bytes * gray.height() as usize];
+  for (x, y, pixel) in gray.enumerate_pixels() {
+    if pixel[0] == 255 {
+      packed[y as usize * row_bytes + x as usize / 8] |= 0x80 >> (x % 8);
+    }
+  }
+  packed
+}
+
+#[cfg(test)]
+mod tests {
+  use image::{GrayImage, Luma, RgbImage};
+
+  use super::*;
+  use crate::test_support::TempDir;
+
+  #[test]
+  fn bilevel_rows_are_packed_most_significant_bit_first() {
+    let gray = GrayImage::from_raw(8, 2, vec![
+      255, 0, 0, 0, 0, 0, 0, 255,
+      0, 255, 255, 0, 0, 0, 0, 0
+    ]).unwrap();
+    assert_eq!(pack_bilevel(&gray), vec![0b1000_0001, 0b0110_0000]);
+  }
+
+  #[test]
+  fn bilevel_rows_are_padded_to_whole_bytes() {
+    // The ninth pixel of each row starts a byte of its own
+    let gray = GrayImage::from_fn(9, 2, |x, y| Luma([if x == 8 || (y == 1 && x == 0) { 255 } else { 0 }]));
+    assert_eq!(pack_bilevel(&gray), vec![0b0000_0000, 0b1000_0000, 0b1000_0000, 0b1000_0000]);
+  }
+
+  #[test]
+  fn text_is_mostly_paper_and_ink() {
+    let page = GrayImage::from_fn(100, 100, |x, _| Luma([if x < 20 { 10 } else { 240 }]));
+    assert!(looks_like_text(&page));
+    let photo = GrayImage::from_fn(100, 100, |x, y| Luma([((x + y) * 255 / 198) as u8]));
+    assert!(!looks_like_text(&photo));
+    let night = GrayImage::from_pixel(100, 100, Luma([5]));
+    assert!(!looks_like_text(&night));
+  }
+
+  #[test]
+  fn lossless_pages_are_saved_as_they_are() {
+    let dir = TempDir::new();
+    let rgb = DynamicImage::ImageRgb8(RgbImage::from_fn(9, 5, |x, y| image::Rgb([x as u8 * 20, y as u8 * 40, 7])));
+    let gray = DynamicImage::ImageLuma8(GrayImage::from_fn(9, 5, |x, y| Luma([(x * 5 + y) as u8])));
+    let bilevel = DynamicImage::ImageLuma8(GrayImage::from_fn(9, 5, |x, y| Luma([if (x + y) % 3 == 0 { 255 } else { 0 }])));
+
+    for (name, page) in [("rgb", &rgb), ("gray", &gray), ("bilevel", &bilevel)] {
+      let encoded = EncodedImage::encode(page, ImageEncoding::Flate, 85, Path::new("photo.jpg")).unwrap();
+      assert_eq!(encoded.filter, RasterFilter::Flate);
+      assert_eq!(encoded.bits_per_component, if name == "bilevel" { 1 } else { 8 });
+      let path = encoded.save(&dir.join(name)).unwrap();
+      assert_eq!(path, dir.join(format!("{}.png", name)));
+      assert_eq!(image::open(&path).unwrap().as_bytes(), page.as_bytes(), "{}", name);
+    }
+  }
+
+  #[test]
+  fn jpeg_pages_are_saved_as_jpeg() {
+    let dir = TempDir::new();
+    let photo = DynamicImage::ImageLuma8(GrayImage::from_fn(16, 16, |x, y| Luma([(x * 8 + y) as u8])));
+    let encoded = EncodedImage::encode(&photo, ImageEncoding::Auto, 85, Path::new("photo.jpg")).unwrap();
+    assert_eq!(encoded.filter, RasterFilter::Dct);
+    let path = encoded.save(&dir.join("page-001")).unwrap();
+    assert_eq!(path, dir.join("page-001.jpg"));
+    assert_eq!(fs::read(&path).unwrap(), encoded.data);
+    assert_eq!(image::open(&path).unwrap().dimensions(), (16, 16));
+  }
+}
@@ -0,0 +1,179 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use printpdf::Mm;
+
+use crate::builder::ByteSize;
+use crate::page::Crop;
+
+/// Everything that can go wrong while building a book.
+///
+/// Errors that concern a single file carry its path so the caller can tell
+/// the user which photo needs attention.
+#[derive(Debug)]
+pub enum BookerError {
+  /// Reading or writing a file failed.
+  Io { path: PathBuf, source: io::Error },
+  /// Writing the book to the output writer failed.
+  Output { source: io::Error },
+  /// The file has no readable EXIF data.
+  Exif { path: PathBuf, source: exif::Error },
+  /// The EXIF data has no capture date.
+  MissingDate { path: PathBuf },
+  /// The capture date could not be understood.
+  InvalidDate { path: PathBuf, value: String },
+  /// The image could not be decoded.
+  Decode { path: PathBuf, source: image::ImageError },
+  /// A rendered page could not be encoded.
+  Encode { path: PathBuf, source: image::ImageError },
+  /// A crop rectangle lies outside the image.
+  InvalidCrop { path: PathBuf, crop: Crop },
+  /// The book manifest could not be read or is invalid.
+  Manifest { path: PathBuf, message: String },
+  /// The PDF could not be produced.
+  Pdf { source: printpdf::Error },
+  /// The finished PDF could not be post-processed.
+  PdfRewrite { source: lopdf::Error },
+  /// The PDF stays larger than the size limit even at the lowest settings.
+  TooLarge { size: ByteSize, limit: ByteSize },
+  /// The margins leave no room for the image on a page of this size.
+  MarginsTooLarge { width: Mm, height: Mm },
+  /// A page raster of this many pixels would take more memory than allowed.
+  RasterTooLarge { width: u32, height: u32, limit: ByteSize }
+}
+
+pub type Result<T> = std::result::Result<T, BookerError>;
+
+impl BookerError {
+  pub(crate) fn io<P: AsRef<Path>>(path: P, source: io::Error) -> BookerError {
+    BookerError::Io { path: path.as_ref().to_path_buf(), source }
+  }
+
+  /// The file this error is about, if any.
+  pub fn path(&self) -> Option<&Path> {
+    match self {
+      BookerError::Io { path, .. }
+      | BookerError::Exif { path, .. }
+      | BookerError::MissingDate { path }
+      | BookerError::InvalidDate { path, .. }
+      | BookerError::Decode { path, .. }
+      | BookerError::Encode { path, .. }
+      | BookerError::InvalidCrop { path, .. }
+      | BookerError::Manifest { path, .. } => Some(path),
+      BookerError::Output { .. }
+      | BookerError::Pdf { .. }
+      | BookerError::PdfRewrite { .. }
+      | BookerError::TooLarge { .. }
+      | BookerError::MarginsTooLarge { .. }
+      | BookerError::RasterTooLarge { .. } => None
+    }
+  }
+}
+
+impl fmt::Display for BookerError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      BookerError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+      BookerError::Output { source } => write!(f, "could not write the book: {}", source),
+      BookerError::Exif { path, source } => write!(f, "{}: could not read EXIF data: {}", path.display(), source),
+      BookerError::MissingDate { path } => write!(f, "{}: no capture date found", path.display()),
+      BookerError::InvalidDate { path, value } => write!(f, "{}: invalid capture date {:?}", path.display(), value),
+      BookerError::Decode { path, source } => write!(f, "{}: could not decode image: {}", path.display(), source),
+      BookerError::Encode { path, source } => write!(f, "{}: could not encode page: {}", path.display(), source),
+      BookerError::InvalidCrop { path, crop } => {
+        write!(f, "{}: crop {}x{}+{}+{} lies outside the image", path.display(), crop.width, crop.height, crop.x, crop.y)
+      },
+      BookerError::Manifest { path, message } => write!(f, "{}: {}", path.display(), message),
+      BookerError::Pdf { source } => write!(f, "could not write PDF: {}", source),
+      BookerError::PdfRewrite { source } => write!(f, "could not finish PDF: {}", source),
+      BookerError::TooLarge { size, limit } => {
+        write!(f, "the book is still {} at the lowest quality and resolution, over the limit of {}", size, limit)
+      },
+      BookerError::MarginsTooLarge { width, height } => {
+        write!(f, "the margins leave no room for the image on a {:.0}x{:.0} mm page", width.0, height.0)
+      },
+      BookerError::RasterTooLarge { width, height, limit } => {
+        write!(f, "pages of {}x{} pixels would need more than {}; use a lower dpi or a smaller page", width, height, limit)
+      }
+    }
+  }
+}
+
+impl Error for BookerError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      BookerError::Io { source, .. } | BookerError::Output { source } => Some(source),
+      BookerError::Exif { source, .. } => Some(source),
+      BookerError::Decode { source, .. } | BookerError::Encode { source, .. } => Some(source),
+      BookerError::Pdf { source } => Some(source),
+      BookerError::PdfRewrite { source } => Some(source),
+      BookerError::MissingDate { .. }
+      | BookerError::InvalidDate { .. }
+      | BookerError::InvalidCrop { .. }
+      | BookerError::Manifest { .. }
+      | BookerError::TooLarge { .. }
+      | BookerError::MarginsTooLarge { .. }
+      | BookerError::RasterTooLarge { .. } => None
+    }
+  }
+}
+
+impl From<printpdf::Error> for BookerError {
+  fn from(source: printpdf::Error) -> BookerError {
+    BookerError::Pdf { source }
+  }
+}
+
+impl From<lopdf::Error> for BookerError {
+  fn from(source: lopdf::Error) -> BookerError {
+    BookerError::PdfRewrite { source }
+  }
+}
+
+/// What to do with an input file that cannot be read, dated or decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+  /// Leave the file out of the book and report it at the end.
+  Skip,
+  /// Stop and return the error.
+  #[default]
+  Fail,
+  /// Put a "missing image" page in the book and report it at the end.
+  Placeholder
+}
+
+impl FromStr for ErrorPolicy {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::result::Result<ErrorPolicy, String> {
+    match s {
+      "skip" => Ok(ErrorPolicy::Skip),
+      "fail" => Ok(ErrorPolicy::Fail),
+      "placeholder" => Ok(ErrorPolicy::Placeholder),
+      _ => Err(format!("unknown error policy {:?}, expected skip, fail or placeholder", s))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn output_errors_have_no_path() {
+    let error = BookerError::Output { source: io::Error::new(io::ErrorKind::BrokenPipe, "pipe closed") };
+    assert_eq!(error.path(), None);
+    assert_eq!(error.to_string(), "could not write the book: pipe closed");
+    assert!(error.source().is_some());
+  }
+
+  #[test]
+  fn file_errors_name_the_file() {
+    let error = BookerError::io("photos/a.jpg", io::Error::new(io::ErrorKind::NotFound, "not found"));
+    assert_eq!(error.path(), Some(Path::new("photos/a.jpg")));
+    assert_eq!(error.to_string(), "photos/a.jpg: not found");
+  }
+}
@@ -0,0 +1,206 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use lopdf::{Dictionary, Object, Stream, StringFormat};
+
+use crate::error::Result;
+
+/// Name and version of this tool, stored as the PDF producer.
+const PRODUCER: &str = concat!("wckfa-booker ", env!("CARGO_PKG_VERSION"));
+
+/// What the PDF says about itself, in its document information dictionary
+/// and its XMP metadata.
+#[derive(Debug, Clone, Default)]
+pub struct DocumentInfo {
+  pub title: String,
+  pub author: Option<String>,
+  /// What the book is about, e.g. the event the photos were taken at.
+  pub subject: Option<String>,
+  /// Comma separated search terms.
+  pub keywords: Option<String>,
+  /// The person or program that put the book together.
+  pub creator: Option<String>,
+  /// When the book was made; the time of writing if not set.
+  pub creation_date: Option<DateTime<FixedOffset>>
+}
+
+impl DocumentInfo {
+  pub fn new<S: Into<String>>(title: S) -> DocumentInfo {
+    DocumentInfo { title: title.into(), ..DocumentInfo::default() }
+  }
+
+  /// The separate terms in `keywords`.
+  fn keyword_list(&self) -> Vec<&str> {
+    self.keywords.iter()
+      .flat_map(|keywords| keywords.split([',', ';']))
+      .map(str::trim)
+      .filter(|keyword| !keyword.is_empty())
+      .collect()
+  }
+
+  /// Completes the information dictionary printpdf wrote and adds a
+  /// matching XMP metadata stream to the catalog.
+  pub(crate) fn write_to(&self, document: &mut lopdf::Document) -> Result<()> {
+    let now = Utc::now().with_timezone(&FixedOffset::east(0));
+    let created = self.creation_date.unwrap_or(now);
+
+    let info_id = document.trailer.get(b"Info")?.as_reference()?;
+    let info = document.get_object_mut(info_id)?.as_dict_mut()?;
+    info.set("Title", text_string(&self.title));
+    let optional = [("Author", &self.author), ("Subject", &self.subject), ("Keywords", &self.keywords), ("Creator", &self.creator)];
+    for (key, value) in optional.iter() {
+      if let Some(value) = value {
+        info.set(*key, text_string(value));
+      }
+    }
+    info.set("Producer", text_string(PRODUCER));
+    info.set("CreationDate", Object::string_literal(pdf_date(&created)));
+    info.set("ModDate", Object::string_literal(pdf_date(&now)));
+
+    let mut dictionary = Dictionary::new();
+    dictionary.set("Type", Object::Name(b"Metadata".to_vec()));
+    dictionary.set("Subtype", Object::Name(b"XML".to_vec()));
+    // Left uncompressed so that tools which do not parse PDF can still find it
+    let xmp = Stream::new(dictionary, self.xmp(&created, &now).into_bytes()).with_compression(false);
+    let xmp_id = document.add_object(xmp);
+    let catalog_id = document.trailer.get(b"Root")?.as_reference()?;
+    document.get_object_mut(catalog_id)?.as_dict_mut()?.set("Metadata", xmp_id);
+    Ok(())
+  }
+
+  /// The XMP packet describing the document.
+  fn xmp(&self, created: &DateTime<FixedOffset>, now: &DateTime<FixedOffset>) -> String {
+    let mut properties = String::new();
+    properties += "   <dc:format>application/pdf</dc:format>\n";
+    properties += &format!("   <dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:title>\n", xml_escape(&self.title));
+    if let Some(author) = &self.author {
+      properties += &format!("   <dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n", xml_escape(author));
+    }
+    if let Some(subject) = &self.subject {
+      properties += &format!("   <dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>\n", xml_escape(subject));
+    }
+    if let Some(keywords) = &self.keywords {
+      let items = self.keyword_list().iter().map(|k| format!("<rdf:li>{}</rdf:li>", xml_escape(k))).collect::<String>();
+      properties += &format!("   <dc:subject><rdf:Bag>{}</rdf:Bag></dc:subject>\n", items);
+      properties += &format!("   <pdf:Keywords>{}</pdf:Keywords>\n", xml_escape(keywords));
+    }
+    if let Some(creator) = &self.creator {
+      properties += &format!("   <xmp:CreatorTool>{}</xmp:CreatorTool>\n", xml_escape(creator));
+    }
+    properties += &format!("   <pdf:Producer>{}</pdf:Producer>\n", xml_escape(PRODUCER));
+    properties += &format!("   <xmp:CreateDate>{}</xmp:CreateDate>\n", xmp_date(created));
+    properties += &format!("   <xmp:ModifyDate>{}</xmp:ModifyDate>\n", xmp_date(now));
+    properties += &format!("   <xmp:MetadataDate>{}</xmp:MetadataDate>\n", xmp_date(now));
+
+    format!(concat!(
+      "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n",
+      "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n",
+      " <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+      "  <rdf:Description rdf:about=\"\"\n",
+      "    xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n",
+      "    xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"\n",
+      "    xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\">\n",
+      "{}",
+      "  </rdf:Description>\n",
+      " </rdf:RDF>\n",
+      "</x:xmpmeta>\n",
+      "<?xpacket end=\"w\"?>"), properties)
+  }
+}
+
+/// A PDF text string: plain bytes for ASCII, UTF-16 with a byte order mark otherwise.
+pub(crate) fn text_string(text: &str) -> Object {
+  if text.is_ascii() {
+    return Object::string_literal(text);
+  }
+  let mut bytes = vec![0xfe, 0xff];
+  bytes.extend(text.encode_utf16().flat_map(|unit| unit.to_be_bytes()));
+  Object::String(bytes, StringFormat::Hexadecimal)
+}
+
+/// A date in the PDF format, e.g. `D:20240316142501+01'00'`.
+fn pdf_date(date: &DateTime<FixedOffset>) -> String {
+  let offset = date.offset().local_minus_utc() / 60;
+  let sign = if offset < 0 { '-' } else { '+' };
+  format!("D:{}{}{:02}'{:02}'", date.format("%Y%m%d%H%M%S"), sign, offset.abs() / 60, offset.abs() % 60)
+}
+
+/// A date in the XMP format, e.g. `2024-03-16T14:25:01+01:00`.
+fn xmp_date(date: &DateTime<FixedOffset>) -> String {
+  date.format("%Y-%m-%dT%H:%M:%S%:z").to_string()
+}
+
+fn xml_escape(text: &str) -> String {
+  text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::TimeZone;
+
+  use super::*;
+
+  fn date(offset_seconds: i32) -> DateTime<FixedOffset> {
+    FixedOffset::east(offset_seconds).ymd(2024, 3, 16).and_hms(14, 25, 1)
+  }
+
+  #[test]
+  fn pdf_dates_carry_the_offset() {
+    assert_eq!(pdf_date(&date(3600)), "D:20240316142501+01'00'");
+    assert_eq!(pdf_date(&date(0)), "D:20240316142501+00'00'");
+    assert_eq!(pdf_date(&date(-(3 * 3600 + 30 * 60))), "D:20240316142501-03'30'");
+    assert_eq!(pdf_date(&date(5 * 3600 + 45 * 60)), "D:20240316142501+05'45'");
+    assert_eq!(xmp_date(&date(-(3 * 3600 + 30 * 60))), "2024-03-16T14:25:01-03:30");
+  }
+
+  #[test]
+  fn text_strings_use_utf16_beyond_ascii() {
+    assert!(matches!(text_string("Spring Seminar"), Object::String(bytes, StringFormat::Literal) if bytes == b"Spring Seminar"));
+    let utf16 = vec![0xfe, 0xff, 0x00, 0x47, 0x00, 0xf6, 0x00, 0x72, 0x00, 0x6c, 0x00, 0x69, 0x00, 0x74, 0x00, 0x7a, 0x00, 0x20, 0x27, 0x13];
+    assert!(matches!(text_string("Görlitz ✓"), Object::String(bytes, StringFormat::Hexadecimal) if bytes == utf16));
+  }
+
+  #[test]
+  fn xml_special_characters_are_escaped() {
+    assert_eq!(xml_escape(r#"Tom & Jerry <"Live">"#), "Tom &amp; Jerry &lt;&quot;Live&quot;&gt;");
+    assert_eq!(xml_escape("&amp;"), "&amp;amp;");
+  }
+
+  #[test]
+  fn xmp_packet_lists_the_set_fields() {
+    let info = DocumentInfo {
+      author: Some("Kim & Lee".to_string()),
+      keywords: Some("kata; sparring, ,grading".to_string()),
+      ..DocumentInfo::new("Spring <Seminar>")
+    };
+    let xmp = info.xmp(&date(3600), &date(0));
+    assert!(xmp.starts_with("<?xpacket begin=\"\u{feff}\""));
+    assert!(xmp.ends_with("<?xpacket end=\"w\"?>"));
+    assert!(xmp.contains("<rdf:li xml:lang=\"x-default\">Spring &lt;Seminar&gt;</rdf:li>"));
+    assert!(xmp.contains("<dc:creator><rdf:Seq><rdf:li>Kim &amp; Lee</rdf:li></rdf:Seq></dc:creator>"));
+    assert!(xmp.contains("<rdf:Bag><rdf:li>kata</rdf:li><rdf:li>sparring</rdf:li><rdf:li>grading</rdf:li></rdf:Bag>"));
+    assert!(xmp.contains("<xmp:CreateDate>2024-03-16T14:25:01+01:00</xmp:CreateDate>"));
+    assert!(xmp.contains("<xmp:ModifyDate>2024-03-16T14:25:01+00:00</xmp:ModifyDate>"));
+    assert!(!xmp.contains("dc:description"));
+    assert!(!xmp.contains("xmp:CreatorTool"));
+  }
+
+  #[test]
+  fn writes_the_information_dictionary_and_metadata() {
+    let (doc, _, _) = printpdf::PdfDocument::new("draft", printpdf::Mm(100.0), printpdf::Mm(100.0), "Layer 1");
+    let mut saved = Vec::new();
+    doc.save(&mut std::io::BufWriter::new(&mut saved)).unwrap();
+    let mut document = lopdf::Document::load_mem(&saved).unwrap();
+
+    let info = DocumentInfo { creation_date: Some(date(-(3 * 3600 + 30 * 60))), ..DocumentInfo::new("Spring Seminar") };
+    info.write_to(&mut document).unwrap();
+    let info_id = document.trailer.get(b"Info").unwrap().as_reference().unwrap();
+    let dictionary = document.get_dictionary(info_id).unwrap();
+    assert_eq!(dictionary.get(b"Title").unwrap().as_str().unwrap(), b"Spring Seminar");
+    assert_eq!(dictionary.get(b"CreationDate").unwrap().as_str().unwrap(), b"D:20240316142501-03'30'");
+    assert_eq!(dictionary.get(b"Producer").unwrap().as_str().unwrap(), PRODUCER.as_bytes());
+
+    let catalog_id = document.trailer.get(b"Root").unwrap().as_reference().unwrap();
+    let metadata_id = document.get_dictionary(catalog_id).unwrap().get(b"Metadata").unwrap().as_reference().unwrap();
+    let metadata = document.get_object(metadata_id).unwrap().as_stream().unwrap();
+    assert!(String::from_utf8_lossy(&metadata.content).contains("<xmp:CreateDate>2024-03-16T14:25:01-03:30</xmp:CreateDate>"));
+  }
+}
@@ -0,0 +1,54 @@
+//! Turns a folder of photos into a PDF book with one image per page.
+//!
+//! The pipeline reads the capture date of every input image, sorts the images
+//! (chronologically by default), renders each one to a page-sized raster and
+//! places the rasters into a PDF. [`BookBuilder`] is the entry point; the
+//! individual stages are exposed as well for tools that need finer control.
+
+extern crate chrono;
+extern crate exif;
+extern crate flate2;
+extern crate glob;
+extern crate image;
+extern crate lopdf;
+extern crate printpdf;
+extern crate toml;
+extern crate walkdir;
+
+pub mod builder;
+pub mod caption;
+pub mod chapter;
+pub mod contents;
+pub mod cover;
+pub mod dates;
+pub mod encode;
+pub mod error;
+pub mod info;
+pub mod manifest;
+pub mod metadata;
+pub mod outline;
+pub mod page;
+pub mod pdf;
+pub mod render;
+pub mod sort;
+mod text;
+
+#[cfg(test)]
+mod test_support;
+
+pub use builder::{Book, BookBuilder, BookReport, ByteSize, InputSource, OutputSink, Progress, SkippedImage};
+pub use caption::CaptionTemplate;
+pub use chapter::{add_chapters, Chapter};
+pub use contents::{add_contents, ContentsEntry};
+pub use cover::{Cover, CoverOptions};
+pub use dates::{parse_date_time, parse_duration, CaptureTime, ClockOffset, DateSource};
+pub use encode::{EncodedImage, ImageEncoding, RasterFilter};
+pub use error::{BookerError, ErrorPolicy};
+pub use info::DocumentInfo;
+pub use manifest::{Manifest, ManifestPage};
+pub use metadata::{list_input_files, process_input_files, retrieve_image_and_metadata, ImageAndMetadata};
+pub use outline::{build_outline, OutlineEntry, OutlineGrouping};
+pub use page::{Crop, FitMode, LandscapePolicy, Margins, Orientation, PageColor, PageOptions, PageSettings, PageSize, Rotation};
+pub use pdf::{write_images_to_pdf_file, BookPage, PageContent};
+pub use render::{render_page, ColorMode, PageRaster};
+pub use sort::{natural_cmp, sort_images, NaturalKey, SortOrder};
@@ -1,52 +1,40 @@
-use std::fmt;
-
 use std::io;
-use std::io::BufWriter;
 use std::io::Write;
-
-use std::fs::File;
-
-use std::path::Path;
+use std::process;
 
 extern crate clap;
 use clap::App;
 use clap::Arg;
 
-extern crate walkdir;
-use walkdir::WalkDir;
-
-extern crate exif;
-
-extern crate chrono;
-use chrono::NaiveDateTime;
-use chrono::NaiveDate;
-
-extern crate tempdir;
-use tempdir::TempDir;
-
-extern crate image;
-use image::imageops;
-use image::imageops::FilterType;
-
-extern crate printpdf;
-use printpdf::*;
-
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
-const AUTHORS: &'static str = env!("CARGO_PKG_AUTHORS");
-const DESCRIPTION: &'static str = env!("CARGO_PKG_DESCRIPTION");
-
-#[derive(Debug)]
-struct ImageAndMetadata {
-  path: String,
-  date_created: NaiveDateTime
-}
-
-impl fmt::Display for ImageAndMetadata {
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "{} {}", self.path, self.date_created)
-  }
-}
-
+extern crate wckfa_booker;
+use wckfa_booker::BookBuilder;
+use wckfa_booker::BookReport;
+use wckfa_booker::ByteSize;
+use wckfa_booker::CaptionTemplate;
+use wckfa_booker::ClockOffset;
+use wckfa_booker::ColorMode;
+use wckfa_booker::CoverOptions;
+use wckfa_booker::DateSource;
+use wckfa_booker::ErrorPolicy;
+use wckfa_booker::FitMode;
+use wckfa_booker::ImageEncoding;
+use wckfa_booker::LandscapePolicy;
+use wckfa_booker::Manifest;
+use wckfa_booker::Margins;
+use wckfa_booker::Orientation;
+use wckfa_booker::OutlineGrouping;
+use wckfa_booker::PageColor;
+use wckfa_booker::PageSettings;
+use wckfa_booker::PageSize;
+use wckfa_booker::OutputSink;
+use wckfa_booker::parse_date_time;
+use wckfa_booker::parse_duration;
+use wckfa_booker::Progress;
+use wckfa_booker::SortOrder;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
+const DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
 
 fn main() {
   // We need the following command line arguments:
@@ -62,7 +50,13 @@ fn main() {
                   .value_name("input_directory")
                   .help("Specifies the input directory from which source images should be taken")
                   .takes_value(true)
-                  .required(true))
+                  .required_unless("manifest")
+                  .conflicts_with("manifest"))
+                .arg(Arg::with_name("manifest")
+                  .long("manifest")
+                  .value_name("book.toml")
+                  .help("Takes the pages, their order and per-page settings from a manifest file instead of an input directory")
+                  .takes_value(true))
                 .arg(Arg::with_name("output")
                   .short("o")
                   .long("output")
@@ -74,150 +68,350 @@ fn main() {
                   .short("t")
                   .long("title")
                   .value_name("title")
-                  .help("Specifies the title of the final PDF")
+                  .help("Specifies the title of the final PDF, overriding the manifest's")
                   .takes_value(true)
-                  .required(true))
+                  .required_unless("manifest"))
+                .arg(Arg::with_name("cover")
+                  .long("cover")
+                  .help("Starts the book with a cover page showing the title, author and the days the photos were taken on"))
+                .arg(Arg::with_name("subtitle")
+                  .long("subtitle")
+                  .value_name("subtitle")
+                  .help("Adds a line under the title on the cover page; implies --cover")
+                  .takes_value(true))
+                .arg(Arg::with_name("cover-image")
+                  .long("cover-image")
+                  .value_name("image")
+                  .help("Shows this photo on the cover page; implies --cover")
+                  .takes_value(true))
+                .arg(Arg::with_name("toc")
+                  .long("toc")
+                  .help("Adds a table of contents, after the cover, linking to the chapters or days and the sections"))
+                .arg(Arg::with_name("caption")
+                  .long("caption")
+                  .value_name("template")
+                  .help("Prints a caption under each photo, e.g. \"{date:%b %d %Y %H:%M} — {description}\". Fields are {date} or {date:FORMAT}, {filename}, {description} (EXIF ImageDescription), {text} (from photo.jpg.txt or photo.jpg.md) and {camera}; manifest captions take precedence")
+                  .takes_value(true)
+                  .validator(|v| v.parse::<CaptionTemplate>().map(|_| ())))
+                .arg(Arg::with_name("author")
+                  .short("a")
+                  .long("author")
+                  .value_name("name")
+                  .help("Specifies the author stored in the PDF")
+                  .takes_value(true))
+                .arg(Arg::with_name("subject")
+                  .long("subject")
+                  .value_name("subject")
+                  .help("Specifies what the book is about, stored in the PDF")
+                  .takes_value(true))
+                .arg(Arg::with_name("keywords")
+                  .long("keywords")
+                  .value_name("keywords")
+                  .help("Comma separated search terms stored in the PDF")
+                  .takes_value(true))
+                .arg(Arg::with_name("creator")
+                  .long("creator")
+                  .value_name("creator")
+                  .help("Specifies who or what put the book together, stored in the PDF")
+                  .takes_value(true))
+                .arg(Arg::with_name("creation-date")
+                  .long("creation-date")
+                  .value_name("date")
+                  .help("Creation date stored in the PDF, e.g. 2024-03-16 or 2024-03-16T14:25:00+01:00; defaults to the date of the earliest photo")
+                  .takes_value(true)
+                  .validator(|v| parse_date_time(&v).map(|_| ())))
+                .arg(Arg::with_name("page-size")
+                  .long("page-size")
+                  .value_name("size")
+                  .help("Paper size: letter, a4, a5, legal, tabloid, or WxH followed by mm or in, e.g. 6x9in")
+                  .takes_value(true)
+                  .default_value("letter")
+                  .validator(|v| v.parse::<PageSize>().map(|_| ())))
+                .arg(Arg::with_name("orientation")
+                  .long("orientation")
+                  .value_name("orientation")
+                  .help("Which way up the pages are")
+                  .takes_value(true)
+                  .possible_values(&["portrait", "landscape"])
+                  .default_value("portrait"))
+                .arg(Arg::with_name("dpi")
+                  .long("dpi")
+                  .value_name("dpi")
+                  .help("Resolution of the page images, e.g. 75 for the screen or 300 for print, at most 1200")
+                  .takes_value(true)
+                  .default_value("75")
+                  .validator(|v| match v.parse::<f64>() {
+                    Ok(dpi) if dpi > 0.0 && dpi <= 1200.0 => Ok(()),
+                    _ => Err(format!("invalid resolution {:?}, expected a positive number up to 1200", v))
+                  }))
+                .arg(Arg::with_name("fit")
+                  .long("fit")
+                  .value_name("mode")
+                  .help("How photos fill the page: contain shows all of it, cover fills the page and crops, stretch distorts")
+                  .takes_value(true)
+                  .possible_values(&["contain", "cover", "stretch"])
+                  .default_value("contain"))
+                .arg(Arg::with_name("margin")
+                  .long("margin")
+                  .value_name("mm")
+                  .help("Page margins in mm: ALL, VERTICAL,HORIZONTAL or TOP,RIGHT,BOTTOM,LEFT")
+                  .takes_value(true)
+                  .default_value("0")
+                  .validator(|v| v.parse::<Margins>().map(|_| ())))
+                .arg(Arg::with_name("background")
+                  .long("background")
+                  .value_name("color")
+                  .help("Color of the page around the photo: white, black, gray or #rrggbb")
+                  .takes_value(true)
+                  .default_value("white")
+                  .validator(|v| v.parse::<PageColor>().map(|_| ())))
+                .arg(Arg::with_name("color")
+                  .long("color")
+                  .value_name("mode")
+                  .help("Keep the photos' colors, convert them to grayscale, or to dithered black and white")
+                  .takes_value(true)
+                  .possible_values(&["full", "grayscale", "bilevel"])
+                  .default_value("grayscale"))
+                .arg(Arg::with_name("landscape")
+                  .long("landscape")
+                  .value_name("policy")
+                  .help("What to do with photos that are not the same way up as the page: rotate them counterclockwise or clockwise, give them a page turned sideways, or scale them down upright")
+                  .takes_value(true)
+                  .possible_values(&["rotate-ccw", "rotate-cw", "landscape-page", "fit-portrait"])
+                  .default_value("rotate-ccw"))
+                .arg(Arg::with_name("image-encoding")
+                  .long("image-encoding")
+                  .value_name("encoding")
+                  .help("How page images are compressed: jpeg for photos, lossless flate for scanned text, or auto to choose per page")
+                  .takes_value(true)
+                  .possible_values(&["jpeg", "flate", "auto"])
+                  .default_value("auto"))
+                .arg(Arg::with_name("jpeg-quality")
+                  .long("jpeg-quality")
+                  .value_name("quality")
+                  .help("Quality of pages stored as JPEG, from 1 to 100")
+                  .takes_value(true)
+                  .default_value("85")
+                  .validator(|v| match v.parse::<u8>() {
+                    Ok(quality) if (1..=100).contains(&quality) => Ok(()),
+                    _ => Err(format!("invalid JPEG quality {:?}, expected a whole number from 1 to 100", v))
+                  }))
+                .arg(Arg::with_name("max-size")
+                  .long("max-size")
+                  .value_name("size")
+                  .help("Largest allowed PDF, e.g. 20MB; lowers JPEG quality and then resolution until the book fits")
+                  .takes_value(true)
+                  .validator(|v| v.parse::<ByteSize>().map(|_| ())))
+                .arg(Arg::with_name("jobs")
+                  .short("j")
+                  .long("jobs")
+                  .value_name("N")
+                  .help("Number of pages to render at the same time; defaults to the number of CPUs")
+                  .takes_value(true)
+                  .validator(|v| match v.parse::<usize>() {
+                    Ok(jobs) if jobs > 0 => Ok(()),
+                    _ => Err(format!("invalid number of jobs {:?}, expected a positive whole number", v))
+                  }))
+                .arg(Arg::with_name("keep-intermediates")
+                  .long("keep-intermediates")
+                  .value_name("dir")
+                  .help("Also saves each rendered page image to this directory, for debugging")
+                  .takes_value(true))
+                .arg(Arg::with_name("on-error")
+                  .long("on-error")
+                  .value_name("policy")
+                  .help("What to do with files that cannot be read or dated: skip them, fail, or insert a placeholder page")
+                  .takes_value(true)
+                  .possible_values(&["skip", "fail", "placeholder"])
+                  .default_value("fail"))
+                .arg(Arg::with_name("date-sources")
+                  .long("date-sources")
+                  .value_name("sources")
+                  .help("Comma separated list of places to take each image's date from, in order of preference")
+                  .takes_value(true)
+                  .use_delimiter(true)
+                  .possible_values(&["exif-original", "exif-digitized", "exif-datetime", "png", "filename", "mtime"]))
+                .arg(Arg::with_name("sort")
+                  .long("sort")
+                  .value_name("order")
+                  .help("How to order the pages")
+                  .takes_value(true)
+                  .possible_values(&["exif-date", "filename-natural", "mtime", "manifest", "none"])
+                  .default_value("exif-date"))
+                .arg(Arg::with_name("chapter-gap")
+                  .long("chapter-gap")
+                  .value_name("duration")
+                  .help("Starts a new chapter, with a divider page and a bookmark, wherever photos are more than this far apart, e.g. 45m or 2h")
+                  .takes_value(true)
+                  .validator(|v| parse_duration(&v).map(|_| ())))
+                .arg(Arg::with_name("outline")
+                  .long("outline")
+                  .value_name("grouping")
+                  .help("Bookmarks by capture date: day, hour (days with hours nested), gap or gap:DURATION such as gap:45m (days with sessions nested), or none. Defaults to day, or none with a manifest")
+                  .takes_value(true)
+                  .validator(|v| v.parse::<OutlineGrouping>().map(|_| ())))
+                .arg(Arg::with_name("reverse")
+                  .long("reverse")
+                  .help("Reverses the page order"))
+                .arg(Arg::with_name("clock-offset")
+                  .long("clock-offset")
+                  .value_name("model=offset")
+                  .help("Corrects the clock of a camera model, e.g. \"Canon EOS 80D=+01:02:30\". May be given more than once")
+                  .takes_value(true)
+                  .multiple(true)
+                  .number_of_values(1)
+                  .validator(|v| v.parse::<ClockOffset>().map(|_| ())))
                 .get_matches();
 
-  // Eventually, we'd like to also accept:
-  // -a <author name>
-
-  // Calling .unwrap() is safe here because "INPUT" is required (if "INPUT" wasn't
+  // Calling .unwrap() is safe here because "output" is required (if "output" wasn't
   // required we could have used an 'if let' to conditionally get the value)
-  let input_dir = matches.value_of("input").unwrap();
   let output_file = matches.value_of("output").unwrap();
-  let doc_title = matches.value_of("title").unwrap();
-
-  let vals_option = process_input_files(input_dir);
-  let mut vals = vals_option.unwrap();
-
-  // Sort vals by datetime
-  vals.sort_by(|a, b| a.date_created.partial_cmp(&b.date_created).unwrap());
-
-  // Create a temporary directory to write to
-  let tmp_dir = TempDir::new("wckfa-booker").unwrap();
-
-  let total_images = &vals.len();
-
-  let mut next_page = 1;
-  for next_value in vals {
-    print!("Processing page {} of {}...", next_page, total_images);
-    io::stdout().flush().ok().expect("Could not flush stdout");
-
-    let page_name = format!("page-{:03}.jpg", next_page);
-
-    // output a grayscale version of the image to the temporary directory
-    let img = image::open(&next_value.path).unwrap();
-    let rgb16 = img.to_rgb8();
-    let (width, height) = rgb16.dimensions();
-    let working_image;
-    if width > height {
-      // Image is in landscape mode. We need to rotate it.
-      working_image = imageops::rotate270(&rgb16);
-    } else {
-      working_image = rgb16;
+  // possible_values guarantees this parses
+  let on_error: ErrorPolicy = matches.value_of("on-error").unwrap().parse().unwrap();
+  let sort_order: SortOrder = matches.value_of("sort").unwrap().parse().unwrap();
+  let orientation: Orientation = matches.value_of("orientation").unwrap().parse().unwrap();
+  // validator guarantees this parses
+  let page_size: PageSize = matches.value_of("page-size").unwrap().parse().unwrap();
+  let dpi: f64 = matches.value_of("dpi").unwrap().parse().unwrap();
+  let margins: Margins = matches.value_of("margin").unwrap().parse().unwrap();
+  let mut page_settings = PageSettings::new(page_size, orientation, dpi).with_margins(margins);
+  page_settings.fit = matches.value_of("fit").unwrap().parse::<FitMode>().unwrap();
+  page_settings.background = matches.value_of("background").unwrap().parse::<PageColor>().unwrap();
+  page_settings.color = matches.value_of("color").unwrap().parse::<ColorMode>().unwrap();
+  page_settings.landscape = matches.value_of("landscape").unwrap().parse::<LandscapePolicy>().unwrap();
+  page_settings.encoding = matches.value_of("image-encoding").unwrap().parse::<ImageEncoding>().unwrap();
+  page_settings.jpeg_quality = matches.value_of("jpeg-quality").unwrap().parse().unwrap();
+  if let Err(e) = page_settings.check() {
+    eprintln!("error: {}", e);
+    process::exit(1);
+  }
+  let date_sources: Vec<DateSource> = match matches.values_of("date-sources") {
+    Some(values) => values.map(|v| v.parse().unwrap()).collect(),
+    None => DateSource::DEFAULT_CHAIN.to_vec()
+  };
+
+  let mut builder = BookBuilder::new(OutputSink::File(output_file.into()));
+  if let Some(values) = matches.values_of("clock-offset") {
+    for value in values {
+      // validator guarantees this parses
+      builder = builder.clock_offset(value.parse().unwrap());
     }
-
-    let grayscale_image = imageops::grayscale(&working_image);
-
-    // resize the image to an appropriate size for 8.5x11" paper
-    let resized_image = imageops::resize(&grayscale_image, 638, 826, FilterType::CatmullRom);
-    resized_image.save(tmp_dir.path().join(page_name)).unwrap();
-    next_page = next_page + 1;
-    print!("Done\n");
-    io::stdout().flush().ok().expect("Could not flush stdout");
   }
 
-  write_images_to_pdf_file(tmp_dir.path().to_str().unwrap(), Path::new(output_file), doc_title, total_images);
-
-  // By closing the `TempDir` explicitly, we can check that it has
-  // been deleted successfully. If we don't close it explicitly,
-  // the directory will still be deleted when `tmp_dir` goes out
-  // of scope, but we won't know whether deleting the directory
-  // succeeded.
-  tmp_dir.close().unwrap();
-}
-
-fn write_images_to_pdf_file(input_dir_name: &str, output_file: &Path, doc_title: &str, num_images: &usize) {
-  let (mut doc, first_page_idx, first_layer_idx) = PdfDocument::new(doc_title, Mm(216.0), Mm(279.0), "Layer 1");
-  doc = doc.with_conformance(PdfConformance::Custom(CustomPdfConformance {
-    requires_icc_profile: false,
-    requires_xmp_metadata: false,
-      .. Default::default()
-    }));
-
-  let mut current_page = doc.get_page(first_page_idx);
-  let mut current_layer = current_page.get_layer(first_layer_idx);
-
-  let mut current_image = 1;
-  while current_image <= *num_images {
-    let page_image = format!("page-{:03}.jpg", current_image);
-    let image_file = Path::new(input_dir_name).join(page_image);
-    print!("Writing image {} to PDF file...", image_file.to_str().unwrap());
-    io::stdout().flush().ok().expect("Could not flush stdout");
-
-    let mut image_file = File::open(image_file).unwrap();
-    let image = Image::try_from(image::jpeg::JpegDecoder::new(&mut image_file).unwrap()).unwrap();
-    image.add_to_layer(current_layer.clone(), None, None, None, Some(4.0), Some(4.0), None);
-
-    if current_image + 1 <= *num_images {
-      let (page_idx, layer_idx) = doc.add_page(Mm(216.0), Mm(279.0), format!("Page {}, Layer 1", current_image));
-      current_page = doc.get_page(page_idx);
-      current_layer = current_page.get_layer(layer_idx);
+  // Either the manifest or an input directory is required
+  if let Some(manifest_file) = matches.value_of("manifest") {
+    match Manifest::load(manifest_file) {
+      Ok(manifest) => builder = builder.manifest(manifest),
+      Err(e) => {
+        eprintln!("error: {}", e);
+        process::exit(1);
+      }
     }
-
-    current_image = current_image + 1;
-    print!("Done\n");
+  } else {
+    builder = builder.input_directory(matches.value_of("input").unwrap());
   }
-
-  doc.save(&mut BufWriter::new(File::create(output_file).unwrap())).unwrap();
-}
-
-fn process_input_files(input: &str) -> Result<Vec<ImageAndMetadata>, exif::Error> {
-  // Process each entry in the input directory and determine its size and when it was created.
-  let partitioned_files = WalkDir::new(input)
-    .into_iter()
-    .filter_map(|e| {
-      e.ok()
-    })
-    .filter(|e| {
-      !e.file_type().is_dir()
+  // The manifest sets the sort order, so only an explicit --sort overrides it
+  if matches.occurrences_of("sort") > 0 || !matches.is_present("manifest") {
+    builder = builder.sort_order(sort_order);
+  }
+  if let Some(grouping) = matches.value_of("outline") {
+    // validator guarantees this parses
+    builder = builder.outline(grouping.parse().unwrap());
+  }
+  if let Some(gap) = matches.value_of("chapter-gap") {
+    // validator guarantees this parses; parse_duration rejects gaps too long to represent
+    builder = builder.chapter_gap(parse_duration(gap).unwrap());
+  }
+  if let Some(doc_title) = matches.value_of("title") {
+    builder = builder.title(doc_title);
+  }
+  if matches.is_present("cover") || matches.is_present("subtitle") || matches.is_present("cover-image") {
+    builder = builder.cover(CoverOptions {
+      subtitle: matches.value_of("subtitle").map(String::from),
+      image: matches.value_of("cover-image").map(Into::into)
     });
-
-  let mut v: Vec<ImageAndMetadata> = Vec::new();
-
-  for entry in partitioned_files {
-    let imamd = retrieve_image_and_metadata(&entry.path().display().to_string());
-
-    v.push(imamd?);
+  }
+  if matches.is_present("toc") {
+    builder = builder.table_of_contents(true);
+  }
+  if let Some(template) = matches.value_of("caption") {
+    // validator guarantees this parses, without date formats that need a time zone
+    builder = builder.caption(template.parse().unwrap());
+  }
+  if let Some(author) = matches.value_of("author") {
+    builder = builder.author(author);
+  }
+  if let Some(subject) = matches.value_of("subject") {
+    builder = builder.subject(subject);
+  }
+  if let Some(keywords) = matches.value_of("keywords") {
+    builder = builder.keywords(keywords);
+  }
+  if let Some(creator) = matches.value_of("creator") {
+    builder = builder.creator(creator);
+  }
+  if let Some(date) = matches.value_of("creation-date") {
+    // validator guarantees this parses
+    builder = builder.creation_date(parse_date_time(date).unwrap());
+  }
+  if let Some(dir) = matches.value_of("keep-intermediates") {
+    builder = builder.keep_intermediates(dir);
+  }
+  if let Some(limit) = matches.value_of("max-size") {
+    // validator guarantees this parses
+    builder = builder.max_size(limit.parse().unwrap());
+  }
+  if let Some(jobs) = matches.value_of("jobs") {
+    // validator guarantees this parses
+    builder = builder.jobs(jobs.parse().unwrap());
   }
 
-  return Ok(v);
+  let result = builder
+    .page_settings(page_settings)
+    .date_sources(date_sources)
+    .reverse(matches.is_present("reverse"))
+    .on_error(on_error)
+    .on_progress(print_progress)
+    .build()
+    .write();
+
+  match result {
+    Ok(report) => {
+      if matches.is_present("max-size") {
+        println!("Wrote {} at {} dpi and JPEG quality {}", report.size, report.dpi.round(), report.jpeg_quality);
+      }
+      print_summary(&report, on_error)
+    },
+    Err(e) => {
+      eprintln!("error: {}", e);
+      process::exit(1);
+    }
+  }
 }
 
-fn retrieve_image_and_metadata(image_file_path: &str) -> Result<ImageAndMetadata, exif::Error> {
-  let file = std::fs::File::open(image_file_path)?;
-  let mut bufreader = std::io::BufReader::new(&file);
-  let exifreader = exif::Reader::new();
-  let exif = exifreader.read_from_container(&mut bufreader)?;
-  let f = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY).unwrap();
-  let date_time_str = f.display_value().with_unit(&exif).to_string();
-  let split_date_time : Vec<&str> = date_time_str.split(' ').collect();
-  let split_date : Vec<&str> = split_date_time[0].split('-').collect();
-  let split_time : Vec<&str> = split_date_time[1].split(':').collect();
-  let year = split_date[0].to_string().parse::<i32>().unwrap();
-  let month = split_date[1].to_string().parse::<u32>().unwrap();
-  let day = split_date[2].to_string().parse::<u32>().unwrap();
-  let hours = split_time[0].to_string().parse::<u32>().unwrap();
-  let minutes = split_time[1].to_string().parse::<u32>().unwrap();
-  let seconds = split_time[2].to_string().parse::<u32>().unwrap();
+fn print_summary(report: &BookReport, on_error: ErrorPolicy) {
+  if report.skipped.is_empty() {
+    return;
+  }
 
-  let date_time : NaiveDateTime = NaiveDate::from_ymd(year, month, day).and_hms(hours, minutes, seconds);
+  let action = match on_error {
+    ErrorPolicy::Placeholder => "replaced by a placeholder page",
+    _ => "skipped"
+  };
+  println!("{} of the input files were {}:", report.skipped.len(), action);
+  for skipped in &report.skipped {
+    println!("  {}", skipped.error);
+  }
+}
 
-  return Ok(
-    ImageAndMetadata {
-      path: image_file_path.to_string(),
-      date_created: date_time
+fn print_progress(event: Progress) {
+  match event {
+    Progress::RenderingPage { page, total } => println!("Processing page {} of {}", page, total),
+    Progress::RenderedPage { page, total } => println!("Finished page {} of {}", page, total),
+    Progress::WritingPage { page, total } => println!("Writing page {} of {} to PDF file", page, total),
+    Progress::Shrinking { size, dpi, jpeg_quality } => {
+      println!("The book is {}, too large; trying again at {} dpi and JPEG quality {}", ByteSize(size), dpi.round(), jpeg_quality)
     }
-  );
+  }
+  io::stdout().flush().ok();
 }
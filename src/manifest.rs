@@ -0,0 +1,213 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::{BookerError, Result};
+use crate::page::{Crop, PageOptions, Rotation};
+use crate::sort::file_name_key;
+
+/// A book definition kept in a TOML file, listing its pages in order.
+///
+/// ```toml
+/// title = "Spring Seminar"
+///
+/// [[page]]
+/// image = "cover.jpg"
+/// section = "Opening"
+/// caption = "Welcome"
+///
+/// [[page]]
+/// image = "forms/*.jpg"
+/// rotate = 90
+/// crop = { x = 100, y = 0, width = 2000, height = 3000 }
+/// color = "full"
+/// ```
+///
+/// Image paths are relative to the manifest. A path containing `*`, `?` or
+/// `[` is a glob whose matches are added in natural file name order; its
+/// `section` starts at the first match and the other settings apply to every
+/// match.
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+  /// Title of the book, if the manifest sets one.
+  pub title: Option<String>,
+  /// Every page of the book, in order, with globs already expanded.
+  pub pages: Vec<ManifestPage>
+}
+
+/// One image listed in a [`Manifest`].
+#[derive(Debug, Clone)]
+pub struct ManifestPage {
+  pub image: PathBuf,
+  pub options: PageOptions
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawManifest {
+  title: Option<String>,
+  #[serde(default)]
+  page: Vec<RawPage>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPage {
+  image: String,
+  section: Option<String>,
+  caption: Option<String>,
+  rotate: Option<i32>,
+  crop: Option<RawCrop>,
+  color: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawCrop {
+  x: u32,
+  y: u32,
+  width: u32,
+  height: u32
+}
+
+impl Manifest {
+  /// Reads the manifest at `path` and expands its globs.
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Manifest> {
+    let path = path.as_ref();
+    let invalid = |message: String| BookerError::Manifest { path: path.to_path_buf(), message };
+
+    let text = fs::read_to_string(path).map_err(|e| BookerError::io(path, e))?;
+    let raw: RawManifest = toml::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut pages = Vec::new();
+    for (index, page) in raw.page.into_iter().enumerate() {
+      let invalid_page = |message: String| invalid(format!("page {}: {}", index + 1, message));
+      let RawPage { image: name, section, caption, rotate, crop, color } = page;
+
+      let rotate = match rotate {
+        Some(degrees) => Some(Rotation::from_degrees(degrees)
+          .ok_or_else(|| invalid_page(format!("rotate must be a multiple of 90, not {}", degrees)))?),
+        None => None
+      };
+      let color = match color {
+        Some(color) => Some(color.parse().map_err(invalid_page)?),
+        None => None
+      };
+      let crop = crop.map(|c| Crop { x: c.x, y: c.y, width: c.width, height: c.height });
+      if crop.is_some_and(|c| c.width == 0 || c.height == 0) {
+        return Err(invalid_page("crop must not be empty".to_string()));
+      }
+      let options = PageOptions { section, caption, rotate, crop, color };
+
+      let image = base.join(&name);
+      if !name.contains(['*', '?', '[']) {
+        // Missing files are reported when they are read, so the error policy applies
+        pages.push(ManifestPage { image, options });
+        continue;
+      }
+
+      // Only the manifest's own pattern is a glob, not the directory it lives in
+      let pattern = Path::new(&glob::Pattern::escape(&base.display().to_string())).join(&name).display().to_string();
+      let mut matches = glob::glob(&pattern)
+        .map_err(|e| invalid_page(format!("invalid pattern {:?}: {}", name, e)))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.is_file())
+        .map(|entry| entry.display().to_string())
+        .collect::<Vec<String>>();
+      if matches.is_empty() {
+        return Err(invalid_page(format!("{:?} matches no files", name)));
+      }
+      matches.sort_by_cached_key(|path| file_name_key(path));
+
+      for (n, matched) in matches.into_iter().enumerate() {
+        let mut options = options.clone();
+        if n > 0 {
+          options.section = None;
+        }
+        pages.push(ManifestPage { image: matched.into(), options });
+      }
+    }
+
+    Ok(Manifest { title: raw.title, pages })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_support::{write_png, TempDir};
+
+  /// Writes `text` as a manifest into `dir` and loads it.
+  fn load(dir: &Path, text: &str) -> Result<Manifest> {
+    let path = dir.join("book.toml");
+    fs::write(&path, text).unwrap();
+    Manifest::load(path)
+  }
+
+  fn error_message(result: Result<Manifest>) -> String {
+    match result.unwrap_err() {
+      BookerError::Manifest { message, .. } => message,
+      other => panic!("unexpected error {:?}", other)
+    }
+  }
+
+  #[test]
+  fn globs_expand_in_natural_order() {
+    let dir = TempDir::new();
+    // Glob characters in the manifest's own directory are taken literally
+    let base = dir.join("seminar [2024]");
+    fs::create_dir_all(base.join("forms")).unwrap();
+    for name in ["form10.png", "form9.png"] {
+      write_png(&base.join("forms").join(name), 4, 4, [0, 0, 0]);
+    }
+    fs::write(base.join("forms/notes.txt"), "not a page").unwrap();
+    let manifest = load(&base, r#"
+      title = "Spring Seminar"
+
+      [[page]]
+      image = "cover.jpg"
+
+      [[page]]
+      image = "forms/*.png"
+      section = "Forms"
+      rotate = 90
+    "#).unwrap();
+
+    assert_eq!(manifest.title.as_deref(), Some("Spring Seminar"));
+    let images: Vec<PathBuf> = manifest.pages.iter().map(|page| page.image.clone()).collect();
+    assert_eq!(images, vec![base.join("cover.jpg"), base.join("forms/form9.png"), base.join("forms/form10.png")]);
+    assert_eq!(manifest.pages[1].options.section.as_deref(), Some("Forms"));
+    assert_eq!(manifest.pages[2].options.section, None);
+    assert_eq!(manifest.pages[2].options.rotate, Some(Rotation::Cw90));
+  }
+
+  #[test]
+  fn globs_must_match() {
+    let dir = TempDir::new();
+    let message = error_message(load(dir.path(), "[[page]]\nimage = \"forms/*.png\"\n"));
+    assert_eq!(message, "page 1: \"forms/*.png\" matches no files");
+  }
+
+  #[test]
+  fn rejects_bad_rotation() {
+    let dir = TempDir::new();
+    let message = error_message(load(dir.path(), "[[page]]\nimage = \"a.jpg\"\n\n[[page]]\nimage = \"b.jpg\"\nrotate = 45\n"));
+    assert_eq!(message, "page 2: rotate must be a multiple of 90, not 45");
+  }
+
+  #[test]
+  fn rejects_empty_crop() {
+    let dir = TempDir::new();
+    let text = "[[page]]\nimage = \"a.jpg\"\ncrop = { x = 0, y = 0, width = 0, height = 100 }\n";
+    assert_eq!(error_message(load(dir.path(), text)), "page 1: crop must not be empty");
+  }
+
+  #[test]
+  fn rejects_unknown_keys() {
+    let dir = TempDir::new();
+    let message = error_message(load(dir.path(), "[[page]]\nimage = \"a.jpg\"\nrotation = 90\n"));
+    assert!(message.contains("unknown field `rotation`"), "{}", message);
+  }
+}
@@ -0,0 +1,280 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use walkdir::WalkDir;
+
+use chrono::{DateTime, Duration, FixedOffset, Local, TimeZone, Utc};
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+
+use crate::dates::{self, CaptureTime, DateSource};
+use crate::error::{BookerError, Result};
+
+/// A source image together with the metadata needed to place it in the book.
+#[derive(Debug)]
+pub struct ImageAndMetadata {
+  pub path: String,
+  /// Capture date as shown on the camera's clock.
+  pub date_created: NaiveDateTime,
+  /// Where `date_created` was taken from.
+  pub date_source: DateSource,
+  /// `date_created` with the time zone recorded by the camera, if it recorded one.
+  pub date_zoned: Option<DateTime<FixedOffset>>,
+  /// The EXIF `Model` of the camera that took the image.
+  pub camera_model: Option<String>,
+  /// The EXIF `ImageDescription`, if the file has one.
+  pub description: Option<String>,
+  /// The EXIF `Orientation` (1 to 8) telling how to turn the stored pixels
+  /// upright; 1, nothing to do, if the file has none.
+  pub orientation: u32
+}
+
+impl ImageAndMetadata {
+  /// The moment the image was taken. Images without a recorded time zone are
+  /// assumed to have been taken in the local time zone.
+  pub fn instant(&self) -> DateTime<Utc> {
+    match self.date_zoned {
+      Some(zoned) => zoned.with_timezone(&Utc),
+      None => match Local.from_local_datetime(&self.date_created).earliest() {
+        Some(local) => local.with_timezone(&Utc),
+        None => DateTime::from_utc(self.date_created, Utc)
+      }
+    }
+  }
+
+  /// The capture date as shown in the book, together with its instant.
+  pub fn capture_time(&self) -> CaptureTime {
+    CaptureTime { local: self.date_created, instant: self.instant() }
+  }
+
+  /// Moves the capture date by `correction`, e.g. to fix a camera whose clock
+  /// is wrong. Fails, leaving the date as it was, if the result is out of range.
+  pub fn shift_clock(&mut self, correction: Duration) -> Result<()> {
+    let out_of_range = || BookerError::InvalidDate {
+      path: Path::new(&self.path).to_path_buf(),
+      value: format!("{} corrected by {} seconds", self.date_created, correction.num_seconds())
+    };
+    let date_created = self.date_created.checked_add_signed(correction).ok_or_else(out_of_range)?;
+    let date_zoned = match self.date_zoned {
+      Some(zoned) => Some(zoned.checked_add_signed(correction).ok_or_else(out_of_range)?),
+      None => None
+    };
+    self.date_created = date_created;
+    self.date_zoned = date_zoned;
+    Ok(())
+  }
+}
+
+impl fmt::Display for ImageAndMetadata {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.date_zoned {
+      Some(zoned) => write!(f, "{} {} ({})", self.path, zoned, self.date_source),
+      None => write!(f, "{} {} ({})", self.path, self.date_created, self.date_source)
+    }
+  }
+}
+
+/// Walks `input` recursively and reads the metadata of every file found in it.
+pub fn process_input_files(input: &str, date_sources: &[DateSource]) -> Result<Vec<ImageAndMetadata>> {
+  // Process each entry in the input directory and determine its size and when it was created.
+  let mut v: Vec<ImageAndMetadata> = Vec::new();
+
+  for file in list_input_files(input)? {
+    v.push(retrieve_image_and_metadata(&file, date_sources)?);
+  }
+
+  Ok(v)
+}
+
+/// Lists every file below `input`, recursively, without looking at its contents.
+pub fn list_input_files(input: &str) -> Result<Vec<String>> {
+  let mut v: Vec<String> = Vec::new();
+
+  for entry in WalkDir::new(input) {
+    let entry = entry.map_err(|e| {
+      let path = e.path().unwrap_or_else(|| Path::new(input)).to_path_buf();
+      let source = e.into_io_error()
+        .unwrap_or_else(|| io::Error::other("filesystem loop detected"));
+      BookerError::io(path, source)
+    })?;
+    if entry.file_type().is_dir() {
+      continue;
+    }
+
+    v.push(entry.path().display().to_string());
+  }
+
+  Ok(v)
+}
+
+/// Reads the capture date of a single image file, trying each of `date_sources`
+/// in turn until one of them yields a date.
+pub fn retrieve_image_and_metadata(image_file_path: &str, date_sources: &[DateSource]) -> Result<ImageAndMetadata> {
+  let path = Path::new(image_file_path);
+
+  // The EXIF block is read once up front; the camera model is wanted whichever source wins
+  let (exif, exif_error) = match read_exif(path)? {
+    Ok(exif) => (Some(exif), None),
+    Err(source) => (None, Some(source))
+  };
+  let mut first_error: Option<BookerError> = None;
+
+  for &source in date_sources {
+    let found = match source.exif_tag() {
+      Some(tag) => match exif.as_ref() {
+        Some(exif) => exif_date(path, exif, tag).unwrap_or_else(|e| {
+          first_error.get_or_insert(e);
+          None
+        }),
+        None => None
+      },
+      None => {
+        let date_time = match source {
+          DateSource::Png => dates::png_date(path),
+          DateSource::Filename => dates::filename_date(path),
+          DateSource::Mtime => dates::mtime_date(path),
+          _ => None
+        };
+        date_time.map(|date_time| (date_time, None))
+      }
+    };
+
+    if let Some((date_time, offset)) = found {
+      return Ok(
+        ImageAndMetadata {
+          path: image_file_path.to_string(),
+          date_created: date_time,
+          date_source: source,
+          date_zoned: offset.and_then(|offset| offset.from_local_datetime(&date_time).single()),
+          camera_model: exif.as_ref().and_then(|exif| exif_string(exif, exif::Tag::Model)),
+          description: exif.as_ref().and_then(|exif| exif_string(exif, exif::Tag::ImageDescription)),
+          orientation: exif.as_ref().and_then(exif_orientation).unwrap_or(1)
+        }
+      );
+    }
+  }
+
+  // Unreadable EXIF data is the most useful thing to report if EXIF dates were wanted
+  let uses_exif = date_sources.iter().any(|source| source.exif_tag().is_some());
+  let exif_error = exif_error
+    .filter(|_| uses_exif)
+    .map(|source| BookerError::Exif { path: path.to_path_buf(), source });
+  Err(exif_error.or(first_error).unwrap_or_else(|| BookerError::MissingDate { path: path.to_path_buf() }))
+}
+
+/// Opens `path` and parses its EXIF block. Only failing to open the file is an
+/// error here; a file without EXIF data simply has no EXIF dates.
+fn read_exif(path: &Path) -> Result<std::result::Result<exif::Exif, exif::Error>> {
+  let file = std::fs::File::open(path).map_err(|e| BookerError::io(path, e))?;
+  let mut bufreader = std::io::BufReader::new(&file);
+  let exifreader = exif::Reader::new();
+  Ok(exifreader.read_from_container(&mut bufreader))
+}
+
+/// The raw bytes of an ASCII field, without the terminating NUL.
+fn exif_ascii(exif: &exif::Exif, tag: exif::Tag) -> Option<&[u8]> {
+  match exif.get_field(tag, exif::In::PRIMARY)?.value {
+    exif::Value::Ascii(ref values) => values.iter().find(|v| !v.is_empty()).map(|v| v.as_slice()),
+    _ => None
+  }
+}
+
+fn exif_string(exif: &exif::Exif, tag: exif::Tag) -> Option<String> {
+  let value = String::from_utf8_lossy(exif_ascii(exif, tag)?).trim().to_string();
+  if value.is_empty() { None } else { Some(value) }
+}
+
+/// The `Orientation` tag, if it holds one of the eight defined values.
+fn exif_orientation(exif: &exif::Exif) -> Option<u32> {
+  exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+    .value
+    .get_uint(0)
+    .filter(|orientation| (1..=8).contains(orientation))
+}
+
+/// Reads one of the EXIF date tags together with the time zone offset recorded
+/// for it. A missing tag is `Ok(None)`, a malformed one an error.
+fn exif_date(path: &Path, exif: &exif::Exif, tag: exif::Tag) -> Result<Option<(NaiveDateTime, Option<FixedOffset>)>> {
+  let f = match exif.get_field(tag, exif::In::PRIMARY) {
+    Some(f) => f,
+    None => return Ok(None)
+  };
+  let raw = exif_ascii(exif, tag);
+  let value = match raw {
+    Some(raw) => String::from_utf8_lossy(raw).into_owned(),
+    None => f.display_value().to_string()
+  };
+  let invalid = || BookerError::InvalidDate { path: path.to_path_buf(), value: value.clone() };
+
+  let raw = raw.ok_or_else(invalid)?;
+  let mut date_time = exif::DateTime::from_ascii(raw).map_err(|_| invalid())?;
+
+  // Each date tag has its own sub-second and offset tags; `OffsetTime` is the
+  // general fallback for the offset
+  let (subsec_tag, offset_tag) = match tag {
+    exif::Tag::DateTimeOriginal => (exif::Tag::SubSecTimeOriginal, exif::Tag::OffsetTimeOriginal),
+    exif::Tag::DateTimeDigitized => (exif::Tag::SubSecTimeDigitized, exif::Tag::OffsetTimeDigitized),
+    _ => (exif::Tag::SubSecTime, exif::Tag::OffsetTime)
+  };
+  if let Some(raw) = exif_ascii(exif, subsec_tag) {
+    // Burst shots share a second; a garbled fraction is no reason to drop the date
+    if date_time.parse_subsec(raw).is_err() {
+      date_time.nanosecond = None;
+    }
+  }
+  let naive = naive_date_time(&date_time).ok_or_else(invalid)?;
+
+  let offset = [offset_tag, exif::Tag::OffsetTime].iter()
+    .filter_map(|&offset_tag| exif_ascii(exif, offset_tag))
+    .find_map(|raw| date_time.parse_offset(raw).ok().and(date_time.offset))
+    .and_then(|minutes| FixedOffset::east_opt(minutes as i32 * 60));
+
+  Ok(Some((naive, offset)))
+}
+
+/// Validates the fields of an EXIF date, which `exif` leaves unchecked.
+/// The all-zero date cameras write when their clock was never set is rejected here.
+fn naive_date_time(date_time: &exif::DateTime) -> Option<NaiveDateTime> {
+  NaiveDate::from_ymd_opt(date_time.year as i32, date_time.month as u32, date_time.day as u32)?
+    .and_hms_nano_opt(date_time.hour as u32, date_time.minute as u32, date_time.second as u32,
+                      date_time.nanosecond.unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_support::image;
+
+  #[test]
+  fn shift_clock_rejects_out_of_range() {
+    let date = NaiveDate::from_ymd(2021, 3, 14).and_hms(10, 15, 0);
+    let mut image = image("IMG_0001.jpg", date);
+    image.shift_clock(Duration::hours(-1)).unwrap();
+    assert_eq!(image.date_created, date - Duration::hours(1));
+    assert!(image.shift_clock(Duration::days(1_000_000_000)).is_err());
+    assert_eq!(image.date_created, date - Duration::hours(1));
+  }
+
+  #[test]
+  fn naive_date_time_reads_exif_dates() {
+    let mut date_time = exif::DateTime::from_ascii(b"2021:03:14 10:15:00").unwrap();
+    assert_eq!(naive_date_time(&date_time), Some(NaiveDate::from_ymd(2021, 3, 14).and_hms(10, 15, 0)));
+    date_time.parse_subsec(b"25").unwrap();
+    assert_eq!(naive_date_time(&date_time), Some(NaiveDate::from_ymd(2021, 3, 14).and_hms_milli(10, 15, 0, 250)));
+  }
+
+  #[test]
+  fn naive_date_time_rejects_unset_clock() {
+    let date_time = exif::DateTime::from_ascii(b"0000:00:00 00:00:00").unwrap();
+    assert_eq!(naive_date_time(&date_time), None);
+  }
+
+  #[test]
+  fn naive_date_time_rejects_impossible_fields() {
+    let date_time = exif::DateTime::from_ascii(b"2021:02:30 10:15:00").unwrap();
+    assert_eq!(naive_date_time(&date_time), None);
+    let date_time = exif::DateTime::from_ascii(b"2021:03:14 25:15:00").unwrap();
+    assert_eq!(naive_date_time(&date_time), None);
+  }
+}
@@ -0,0 +1,303 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{Duration, NaiveDateTime, Timelike};
+use lopdf::{Dictionary, Object, ObjectId};
+
+use crate::error::Result;
+use crate::info::text_string;
+use crate::chapter::Chapter;
+use crate::dates::{gap_between, parse_duration, CaptureTime};
+use crate::pdf::{BookPage, PageContent};
+
+/// Gap between photos that starts a new session when none is configured.
+pub const DEFAULT_SESSION_GAP_MINUTES: i64 = 60;
+
+/// How the bookmarks of a book are grouped by capture date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutlineGrouping {
+  /// No date bookmarks; only manifest sections are bookmarked.
+  None,
+  /// One bookmark per day.
+  #[default]
+  Day,
+  /// One bookmark per day, with one nested bookmark per hour.
+  Hour,
+  /// One bookmark per day, with one nested bookmark per session; a session
+  /// ends where the photos are further apart than the gap.
+  Gap(Duration)
+}
+
+impl fmt::Display for OutlineGrouping {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      OutlineGrouping::None => f.write_str("none"),
+      OutlineGrouping::Day => f.write_str("day"),
+      OutlineGrouping::Hour => f.write_str("hour"),
+      OutlineGrouping::Gap(gap) if gap.num_seconds() % 60 == 0 => write!(f, "gap:{}m", gap.num_minutes()),
+      OutlineGrouping::Gap(gap) => write!(f, "gap:{}s", gap.num_seconds())
+    }
+  }
+}
+
+impl FromStr for OutlineGrouping {
+  type Err = String;
+
+  /// Parses `none`, `day`, `hour`, `gap` or `gap:DURATION`, with the duration
+  /// written as for [`parse_duration`], e.g. `gap:45m`.
+  fn from_str(s: &str) -> std::result::Result<OutlineGrouping, String> {
+    let invalid = || format!("unknown outline grouping {:?}, expected none, day, hour, gap or gap:DURATION", s);
+    match s {
+      "none" => Ok(OutlineGrouping::None),
+      "day" => Ok(OutlineGrouping::Day),
+      "hour" => Ok(OutlineGrouping::Hour),
+      "gap" => Ok(OutlineGrouping::Gap(Duration::minutes(DEFAULT_SESSION_GAP_MINUTES))),
+      _ => {
+        let gap = parse_duration(s.strip_prefix("gap:").ok_or_else(invalid)?)?;
+        if gap <= Duration::zero() {
+          return Err(format!("the session gap in {:?} must be longer than zero", s));
+        }
+        Ok(OutlineGrouping::Gap(gap))
+      }
+    }
+  }
+}
+
+/// A bookmark pointing at a page of the book.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutlineEntry {
+  pub title: String,
+  /// Index of the page in the book, from 0.
+  pub page: usize,
+  pub children: Vec<OutlineEntry>
+}
+
+impl OutlineEntry {
+  fn new(title: String, page: usize) -> OutlineEntry {
+    OutlineEntry { title, page, children: Vec::new() }
+  }
+}
+
+/// Builds the bookmarks for `pages`: one per chapter with the chapter's date
+/// groups nested, or just the date groups in a book without chapters, and one
+/// for each manifest section placed among them in page order.
+pub fn build_outline(pages: &[BookPage], grouping: OutlineGrouping) -> Vec<OutlineEntry> {
+  let chapters: Vec<(usize, &Chapter)> = pages.iter().enumerate()
+    .filter_map(|(index, page)| match &page.content {
+      PageContent::Divider(chapter) => Some((index, chapter)),
+      _ => None
+    })
+    .collect();
+
+  let mut outline = if chapters.is_empty() {
+    date_groups(pages, 0, grouping)
+  } else {
+    chapters.iter().enumerate().map(|(n, &(first, chapter))| {
+      let end = chapters.get(n + 1).map_or(pages.len(), |&(next, _)| next);
+      let mut entry = OutlineEntry::new(format!("{}: {}", chapter.title(), chapter.date_range()), first);
+      entry.children = date_groups(&pages[first..end], first, grouping);
+      // A chapter within a single day needs no day bookmark
+      if entry.children.len() == 1 {
+        entry.children = entry.children.remove(0).children;
+      }
+      entry
+    }).collect()
+  };
+
+  let sections = pages.iter().enumerate()
+    .filter_map(|(index, page)| page.section.as_ref().map(|section| OutlineEntry::new(section.clone(), index)));
+  outline.extend(sections);
+  // Stable, so a chapter or day comes before a section starting on the same page
+  outline.sort_by_key(|entry| entry.page);
+  outline
+}
+
+/// The date bookmarks for `pages`, which start at page `offset` of the book.
+/// Days and hours follow the cameras' clocks, while session gaps are measured
+/// between the moments the photos were taken. Pages without a capture date
+/// stay in the group before them.
+fn date_groups(pages: &[BookPage], offset: usize, grouping: OutlineGrouping) -> Vec<OutlineEntry> {
+  let mut days: Vec<OutlineEntry> = Vec::new();
+  if grouping == OutlineGrouping::None {
+    return days;
+  }
+
+  let mut previous: Option<CaptureTime> = None;
+  // Earliest and latest photo of the current session
+  let mut session = (NaiveDateTime::from_timestamp(0, 0), NaiveDateTime::from_timestamp(0, 0));
+  for (index, time) in pages.iter().enumerate().filter_map(|(i, page)| page.date.map(|d| (offset + i, d))) {
+    let date = time.local;
+    let new_day = previous.is_none_or(|p| p.local.date() != date.date());
+    let new_session = new_day || match (grouping, previous) {
+      (OutlineGrouping::Hour, Some(p)) => p.local.hour() != date.hour(),
+      (OutlineGrouping::Gap(gap), Some(p)) => gap_between(&p, &time) > gap,
+      _ => false
+    };
+
+    if new_day {
+      days.push(OutlineEntry::new(date.format("%A, %-d %B %Y").to_string(), index));
+    }
+    let day = days.last_mut().unwrap();
+    match grouping {
+      OutlineGrouping::Hour if new_session => day.children.push(OutlineEntry::new(date.format("%H:00").to_string(), index)),
+      OutlineGrouping::Gap(_) => {
+        if new_session {
+          session = (date, date);
+          day.children.push(OutlineEntry::new(String::new(), index));
+        }
+        session = (session.0.min(date), session.1.max(date));
+        // Sessions are named after the times of their earliest and latest photo
+        let (start, end) = (session.0.format("%H:%M").to_string(), session.1.format("%H:%M").to_string());
+        day.children.last_mut().unwrap().title = if start == end { start } else { format!("{}–{}", start, end) };
+      },
+      _ => {}
+    }
+    previous = Some(time);
+  }
+  // A single session says nothing the day does not
+  for day in days.iter_mut().filter(|day| day.children.len() == 1) {
+    day.children.clear();
+  }
+  days
+}
+
+/// Replaces the (empty) outline of `document` with `entries`. `page_ids`
+/// holds the object of each page of the book, in order.
+pub(crate) fn write_outline(document: &mut lopdf::Document, entries: &[OutlineEntry], page_ids: &[ObjectId]) -> Result<()> {
+  if entries.is_empty() {
+    return Ok(());
+  }
+
+  let catalog_id = document.trailer.get(b"Root")?.as_reference()?;
+  let root_id = match document.get_dictionary(catalog_id)?.get(b"Outlines").and_then(Object::as_reference) {
+    Ok(id) => id,
+    Err(_) => document.new_object_id()
+  };
+  let (first, last) = write_entries(document, entries, root_id, page_ids);
+  let mut root = Dictionary::new();
+  root.set("Type", Object::Name(b"Outlines".to_vec()));
+  root.set("First", first);
+  root.set("Last", last);
+  root.set("Count", entries.len() as i64);
+  document.objects.insert(root_id, Object::Dictionary(root));
+
+  let catalog = document.get_object_mut(catalog_id)?.as_dict_mut()?;
+  catalog.set("Outlines", root_id);
+  catalog.set("PageMode", Object::Name(b"UseOutlines".to_vec()));
+  Ok(())
+}
+
+/// Adds the outline items for `entries` under `parent_id`, nested entries
+/// closed, and returns the first and last item.
+fn write_entries(document: &mut lopdf::Document, entries: &[OutlineEntry], parent_id: ObjectId, page_ids: &[ObjectId]) -> (ObjectId, ObjectId) {
+  let ids: Vec<ObjectId> = entries.iter().map(|_| document.new_object_id()).collect();
+  for (n, entry) in entries.iter().enumerate() {
+    let mut item = Dictionary::new();
+    item.set("Title", text_string(&entry.title));
+    item.set("Parent", parent_id);
+    if n > 0 {
+      item.set("Prev", ids[n - 1]);
+    }
+    if n + 1 < ids.len() {
+      item.set("Next", ids[n + 1]);
+    }
+    if let Some(&page_id) = page_ids.get(entry.page) {
+      item.set("Dest", vec![page_id.into(), Object::Name(b"Fit".to_vec())]);
+    }
+    if !entry.children.is_empty() {
+      let (first, last) = write_entries(document, &entry.children, ids[n], page_ids);
+      item.set("First", first);
+      item.set("Last", last);
+      item.set("Count", -(entry.children.len() as i64));
+    }
+    document.objects.insert(ids[n], Object::Dictionary(item));
+  }
+  (ids[0], ids[ids.len() - 1])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_support::{at, page, zoned_page};
+
+  /// The title and page of each entry, children indented below it.
+  fn titles(entries: &[OutlineEntry]) -> Vec<String> {
+    entries.iter().flat_map(|entry| {
+      let children = titles(&entry.children).into_iter().map(|child| format!("  {}", child));
+      std::iter::once(format!("{} @{}", entry.title, entry.page)).chain(children)
+    }).collect()
+  }
+
+  #[test]
+  fn grouping_parses() {
+    assert_eq!("day".parse::<OutlineGrouping>(), Ok(OutlineGrouping::Day));
+    assert_eq!("gap".parse::<OutlineGrouping>(), Ok(OutlineGrouping::Gap(Duration::minutes(DEFAULT_SESSION_GAP_MINUTES))));
+    assert_eq!("gap:1h30m".parse::<OutlineGrouping>(), Ok(OutlineGrouping::Gap(Duration::minutes(90))));
+    assert_eq!(OutlineGrouping::Gap(Duration::minutes(90)).to_string(), "gap:90m");
+    for text in ["week", "gap:", "gap:90", "gap:0m", "gap:999999999999999d"] {
+      assert!(text.parse::<OutlineGrouping>().is_err(), "{:?} parsed", text);
+    }
+  }
+
+  #[test]
+  fn groups_by_day_and_hour() {
+    let pages = vec![page(Some(at(16, 9, 0))), page(Some(at(16, 9, 40))), page(Some(at(16, 11, 5))), page(Some(at(17, 10, 0)))];
+    assert_eq!(titles(&build_outline(&pages, OutlineGrouping::Day)), vec![
+      "Saturday, 16 March 2024 @0",
+      "Sunday, 17 March 2024 @3"
+    ]);
+    assert_eq!(titles(&build_outline(&pages, OutlineGrouping::Hour)), vec![
+      "Saturday, 16 March 2024 @0",
+      "  09:00 @0",
+      "  11:00 @2",
+      "Sunday, 17 March 2024 @3"
+    ]);
+    assert!(build_outline(&pages, OutlineGrouping::None).is_empty());
+  }
+
+  #[test]
+  fn groups_sessions_by_gap() {
+    let pages = vec![page(Some(at(16, 9, 0))), page(None), page(Some(at(16, 9, 40))), page(Some(at(16, 11, 5)))];
+    assert_eq!(titles(&build_outline(&pages, OutlineGrouping::Gap(Duration::hours(1)))), vec![
+      "Saturday, 16 March 2024 @0",
+      "  09:00–09:40 @0",
+      "  11:05 @3"
+    ]);
+  }
+
+  #[test]
+  fn groups_reversed_pages() {
+    let pages = vec![page(Some(at(17, 10, 0))), page(Some(at(16, 11, 5))), page(Some(at(16, 9, 40))), page(Some(at(16, 9, 0)))];
+    assert_eq!(titles(&build_outline(&pages, OutlineGrouping::Gap(Duration::hours(1)))), vec![
+      "Sunday, 17 March 2024 @0",
+      "Saturday, 16 March 2024 @1",
+      "  11:05 @1",
+      "  09:00–09:40 @2"
+    ]);
+  }
+
+  #[test]
+  fn session_gaps_are_measured_across_time_zones() {
+    // The second camera is set an hour ahead: its photos follow the first
+    // camera's within minutes, and the last one comes two hours later
+    let pages = vec![zoned_page(at(16, 9, 0), 0), zoned_page(at(16, 10, 20), 1), zoned_page(at(16, 12, 20), 1)];
+    assert_eq!(titles(&build_outline(&pages, OutlineGrouping::Gap(Duration::hours(1)))), vec![
+      "Saturday, 16 March 2024 @0",
+      "  09:00–10:20 @0",
+      "  12:20 @2"
+    ]);
+  }
+
+  #[test]
+  fn sections_follow_the_date_on_the_same_page() {
+    let mut pages = vec![page(None), page(Some(at(16, 9, 0))), page(Some(at(17, 9, 0)))];
+    pages[0].section = Some("Foreword".to_string());
+    pages[2].section = Some("Second day".to_string());
+    assert_eq!(titles(&build_outline(&pages, OutlineGrouping::Day)), vec![
+      "Foreword @0",
+      "Saturday, 16 March 2024 @1",
+      "Sunday, 17 March 2024 @2",
+      "Second day @2"
+    ]);
+  }
+}
@@ -0,0 +1,514 @@
+use std::fmt;
+use std::str::FromStr;
+
+use printpdf::Mm;
+
+use crate::builder::ByteSize;
+use crate::caption::{caption_column, wrap_caption, CAPTION_LINE_HEIGHT};
+use crate::encode::{ImageEncoding, DEFAULT_JPEG_QUALITY};
+use crate::error::{BookerError, Result};
+use crate::render::ColorMode;
+
+/// Resolution printpdf assumes for an image placed without an explicit one.
+const PLACEMENT_DPI: f64 = 300.0;
+
+const MM_PER_INCH: f64 = 25.4;
+
+/// Longest side a PDF page may have: 200 in, or 14400 pt.
+const MAX_PAGE_LENGTH: Mm = Mm(200.0 * MM_PER_INCH);
+
+/// Most memory a single page raster may take as 8-bit RGB, enough for every
+/// preset size at 1200 dpi. Each render worker holds one at a time.
+pub const MAX_RASTER_SIZE: ByteSize = ByteSize(1_000_000_000);
+
+/// A paper size, always given in portrait orientation.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PageSize {
+  /// US Letter, 8.5 × 11 in, which is what all of our printed books have used so far.
+  #[default]
+  Letter,
+  /// ISO A4, 210 × 297 mm.
+  A4,
+  /// ISO A5, 148 × 210 mm.
+  A5,
+  /// US Legal, 8.5 × 14 in.
+  Legal,
+  /// US Tabloid, 11 × 17 in.
+  Tabloid,
+  /// Any other width and height.
+  Custom(Mm, Mm)
+}
+
+impl PageSize {
+  /// Width and height of the paper in portrait orientation.
+  pub fn dimensions(self) -> (Mm, Mm) {
+    let inches = |w: f64, h: f64| (Mm(w * MM_PER_INCH), Mm(h * MM_PER_INCH));
+    match self {
+      PageSize::Letter => inches(8.5, 11.0),
+      PageSize::A4 => (Mm(210.0), Mm(297.0)),
+      PageSize::A5 => (Mm(148.0), Mm(210.0)),
+      PageSize::Legal => inches(8.5, 14.0),
+      PageSize::Tabloid => inches(11.0, 17.0),
+      PageSize::Custom(width, height) => (width, height)
+    }
+  }
+}
+
+impl fmt::Display for PageSize {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PageSize::Letter => f.write_str("letter"),
+      PageSize::A4 => f.write_str("a4"),
+      PageSize::A5 => f.write_str("a5"),
+      PageSize::Legal => f.write_str("legal"),
+      PageSize::Tabloid => f.write_str("tabloid"),
+      PageSize::Custom(width, height) => write!(f, "{}x{}mm", width.0, height.0)
+    }
+  }
+}
+
+impl FromStr for PageSize {
+  type Err = String;
+
+  /// Parses a preset name or `WxH` followed by `mm` or `in`, e.g. `210x297mm`
+  /// or `6x9in`, no side longer than 200 in.
+  fn from_str(s: &str) -> std::result::Result<PageSize, String> {
+    let invalid = || format!("invalid page size {:?}, expected letter, a4, a5, legal, tabloid or WxH(mm|in) up to 200in", s);
+
+    let s = s.trim().to_lowercase();
+    match s.as_str() {
+      "letter" => return Ok(PageSize::Letter),
+      "a4" => return Ok(PageSize::A4),
+      "a5" => return Ok(PageSize::A5),
+      "legal" => return Ok(PageSize::Legal),
+      "tabloid" => return Ok(PageSize::Tabloid),
+      _ => {}
+    }
+
+    let (value, unit) = if let Some(value) = s.strip_suffix("mm") {
+      (value, 1.0)
+    } else if let Some(value) = s.strip_suffix("in") {
+      (value, MM_PER_INCH)
+    } else {
+      return Err(invalid());
+    };
+    let (width, height) = value.split_once('x').ok_or_else(invalid)?;
+    let length = |v: &str| v.trim().parse::<f64>().ok()
+      .map(|n| n * unit)
+      .filter(|mm| mm.is_finite() && *mm > 0.0 && *mm <= MAX_PAGE_LENGTH.0)
+      .map(Mm);
+    match (length(width), length(height)) {
+      (Some(width), Some(height)) => Ok(PageSize::Custom(width, height)),
+      _ => Err(invalid())
+    }
+  }
+}
+
+/// Which way up the pages are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Orientation {
+  #[default]
+  Portrait,
+  Landscape
+}
+
+impl FromStr for Orientation {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::result::Result<Orientation, String> {
+    match s {
+      "portrait" => Ok(Orientation::Portrait),
+      "landscape" => Ok(Orientation::Landscape),
+      _ => Err(format!("unknown orientation {:?}, expected portrait or landscape", s))
+    }
+  }
+}
+
+/// What happens to an image whose orientation differs from the page's, such as
+/// a landscape photo in a portrait book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LandscapePolicy {
+  /// Turn the image a quarter turn counterclockwise to fill the page.
+  #[default]
+  RotateCcw,
+  /// Turn the image a quarter turn clockwise to fill the page.
+  RotateCw,
+  /// Keep the image upright and give it a page turned the same way.
+  LandscapePage,
+  /// Keep the image upright and scale it down to fit the page.
+  FitPortrait
+}
+
+impl FromStr for LandscapePolicy {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::result::Result<LandscapePolicy, String> {
+    match s {
+      "rotate-ccw" => Ok(LandscapePolicy::RotateCcw),
+      "rotate-cw" => Ok(LandscapePolicy::RotateCw),
+      "landscape-page" => Ok(LandscapePolicy::LandscapePage),
+      "fit-portrait" => Ok(LandscapePolicy::FitPortrait),
+      _ => Err(format!("unknown landscape policy {:?}, expected rotate-ccw, rotate-cw, landscape-page or fit-portrait", s))
+    }
+  }
+}
+
+/// How an image is scaled into the area inside the page margins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FitMode {
+  /// Show the whole image, centered, leaving bands of background on two sides.
+  #[default]
+  Contain,
+  /// Fill the whole area, cutting off the parts of the image that stick out.
+  Cover,
+  /// Fill the whole area, distorting the image if its shape differs.
+  Stretch
+}
+
+impl FromStr for FitMode {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::result::Result<FitMode, String> {
+    match s {
+      "contain" => Ok(FitMode::Contain),
+      "cover" => Ok(FitMode::Cover),
+      "stretch" => Ok(FitMode::Stretch),
+      _ => Err(format!("unknown fit mode {:?}, expected contain, cover or stretch", s))
+    }
+  }
+}
+
+/// Blank space kept around the image on each side of the page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Margins {
+  pub top: Mm,
+  pub right: Mm,
+  pub bottom: Mm,
+  pub left: Mm
+}
+
+impl Default for Margins {
+  fn default() -> Margins {
+    Margins::uniform(Mm(0.0))
+  }
+}
+
+impl Margins {
+  pub fn uniform(margin: Mm) -> Margins {
+    Margins { top: margin, right: margin, bottom: margin, left: margin }
+  }
+}
+
+impl FromStr for Margins {
+  type Err = String;
+
+  /// Parses millimetres as `ALL`, `VERTICAL,HORIZONTAL` or `TOP,RIGHT,BOTTOM,LEFT`.
+  fn from_str(s: &str) -> std::result::Result<Margins, String> {
+    let invalid = || format!("invalid margins {:?}, expected ALL, VERTICAL,HORIZONTAL or TOP,RIGHT,BOTTOM,LEFT in mm", s);
+
+    let values = s.split(',')
+      .map(|v| v.trim().parse::<f64>().ok().filter(|n| n.is_finite() && *n >= 0.0).map(Mm))
+      .collect::<Option<Vec<Mm>>>()
+      .ok_or_else(invalid)?;
+    match values.as_slice() {
+      [all] => Ok(Margins::uniform(*all)),
+      [vertical, horizontal] => Ok(Margins { top: *vertical, right: *horizontal, bottom: *vertical, left: *horizontal }),
+      [top, right, bottom, left] => Ok(Margins { top: *top, right: *right, bottom: *bottom, left: *left }),
+      _ => Err(invalid())
+    }
+  }
+}
+
+/// An RGB color, e.g. for the page background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageColor {
+  pub red: u8,
+  pub green: u8,
+  pub blue: u8
+}
+
+impl PageColor {
+  pub const WHITE: PageColor = PageColor { red: 255, green: 255, blue: 255 };
+  pub const BLACK: PageColor = PageColor { red: 0, green: 0, blue: 0 };
+
+  /// Black or white, whichever is easier to read on this color.
+  pub fn contrasting(self) -> PageColor {
+    let luma = 0.299 * self.red as f64 + 0.587 * self.green as f64 + 0.114 * self.blue as f64;
+    if luma >= 128.0 { PageColor::BLACK } else { PageColor::WHITE }
+  }
+}
+
+impl FromStr for PageColor {
+  type Err = String;
+
+  /// Parses `white`, `black`, `gray` or a hex color such as `#1a1a1a`.
+  fn from_str(s: &str) -> std::result::Result<PageColor, String> {
+    let invalid = || format!("invalid color {:?}, expected white, black, gray or #rrggbb", s);
+
+    match s.to_lowercase().as_str() {
+      "white" => Ok(PageColor::WHITE),
+      "black" => Ok(PageColor::BLACK),
+      "gray" | "grey" => Ok(PageColor { red: 128, green: 128, blue: 128 }),
+      hex => {
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        if hex.len() != 6 || !hex.is_ascii() {
+          return Err(invalid());
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+        Ok(PageColor { red: channel(0)?, green: channel(2)?, blue: channel(4)? })
+      }
+    }
+  }
+}
+
+/// Physical page box, layout and raster size used for every page of the book.
+#[derive(Debug, Clone, Copy)]
+pub struct PageSettings {
+  /// Width of the PDF page.
+  pub width: Mm,
+  /// Height of the PDF page.
+  pub height: Mm,
+  /// Space kept free around the image.
+  pub margins: Margins,
+  /// Width in pixels of the area inside the margins.
+  pub raster_width: u32,
+  /// Height in pixels of the area inside the margins.
+  pub raster_height: u32,
+  /// Scale applied when the raster is placed on the PDF layer.
+  pub image_scale: f64,
+  /// How images are scaled into the area inside the margins.
+  pub fit: FitMode,
+  /// Color of the page around the image.
+  pub background: PageColor,
+  /// How the colors of the photos are reproduced, unless a page overrides it.
+  pub color: ColorMode,
+  /// What happens to images that are not the same way up as the page.
+  pub landscape: LandscapePolicy,
+  /// How the page rasters are compressed.
+  pub encoding: ImageEncoding,
+  /// Quality of page rasters stored as JPEG, from 1 to 100.
+  pub jpeg_quality: u8
+}
+
+/// Height kept free below the image on pages with a one line caption; each
+/// further line adds [`CAPTION_LINE_HEIGHT`].
+pub const CAPTION_SPACE: Mm = Mm(14.0);
+
+/// Raster resolution used when none is configured: fine on screen, soft in print.
+pub const DEFAULT_DPI: f64 = 75.0;
+
+impl PageSettings {
+  /// Settings for pages of the given size, with rasters of `dpi` pixels per
+  /// inch that fill each page.
+  pub fn new(size: PageSize, orientation: Orientation, dpi: f64) -> PageSettings {
+    let (width, height) = match (size.dimensions(), orientation) {
+      ((width, height), Orientation::Portrait) => (width, height),
+      ((width, height), Orientation::Landscape) => (height, width)
+    };
+
+    let mut settings = PageSettings {
+      width,
+      height,
+      margins: Margins::default(),
+      raster_width: 0,
+      raster_height: 0,
+      image_scale: PLACEMENT_DPI / dpi,
+      fit: FitMode::default(),
+      background: PageColor::WHITE,
+      color: ColorMode::default(),
+      landscape: LandscapePolicy::default(),
+      encoding: ImageEncoding::default(),
+      jpeg_quality: DEFAULT_JPEG_QUALITY
+    };
+    settings.update_raster_size();
+    settings
+  }
+
+  /// Renders the pages at `dpi` pixels per inch instead, keeping their size.
+  pub fn with_dpi(mut self, dpi: f64) -> PageSettings {
+    self.image_scale = PLACEMENT_DPI / dpi;
+    self.update_raster_size();
+    self
+  }
+
+  /// Keeps `margins` free around the image, shrinking the raster to match.
+  pub fn with_margins(mut self, margins: Margins) -> PageSettings {
+    self.margins = margins;
+    self.update_raster_size();
+    self
+  }
+
+  /// Keeps enough space free below the image for `caption`, wrapped as it
+  /// is printed, so that it never overlaps the image.
+  pub fn with_caption_space(self, caption: &str) -> PageSettings {
+    let (_, width) = caption_column(&self);
+    let lines = wrap_caption(caption, width).len().max(1);
+    let space = CAPTION_SPACE.0 + (lines - 1) as f64 * CAPTION_LINE_HEIGHT.0;
+    let bottom = Mm(self.margins.bottom.0.max(space));
+    self.with_margins(Margins { bottom, ..self.margins })
+  }
+
+  /// The same settings for a page turned sideways, with width and height swapped.
+  pub fn sideways(mut self) -> PageSettings {
+    std::mem::swap(&mut self.width, &mut self.height);
+    self.update_raster_size();
+    self
+  }
+
+  /// Checks that pages with these settings can be rendered: the margins
+  /// must leave room for the image, also on pages turned sideways, and the
+  /// rasters must fit in [`MAX_RASTER_SIZE`].
+  pub fn check(&self) -> Result<()> {
+    let Margins { top, right, bottom, left } = self.margins;
+    let mut pages = vec![(self.width, self.height)];
+    if self.landscape == LandscapePolicy::LandscapePage {
+      pages.push((self.height, self.width));
+    }
+    for (width, height) in pages {
+      if width.0 - left.0 - right.0 <= 0.0 || height.0 - top.0 - bottom.0 <= 0.0 {
+        return Err(BookerError::MarginsTooLarge { width, height });
+      }
+    }
+
+    let bytes = self.raster_width as u64 * self.raster_height as u64 * 3;
+    if bytes > MAX_RASTER_SIZE.0 {
+      return Err(BookerError::RasterTooLarge { width: self.raster_width, height: self.raster_height, limit: MAX_RASTER_SIZE });
+    }
+    Ok(())
+  }
+
+  /// Pixels per inch of the page rasters.
+  pub fn dpi(&self) -> f64 {
+    PLACEMENT_DPI / self.image_scale
+  }
+
+  /// The printed length of `pixels` raster pixels.
+  pub fn raster_length(&self, pixels: u32) -> Mm {
+    Mm(pixels as f64 / self.dpi() * MM_PER_INCH)
+  }
+
+  fn update_raster_size(&mut self) {
+    let dpi = self.dpi();
+    let pixels = |length: f64| (length / MM_PER_INCH * dpi).round().max(1.0) as u32;
+    self.raster_width = pixels(self.width.0 - self.margins.left.0 - self.margins.right.0);
+    self.raster_height = pixels(self.height.0 - self.margins.top.0 - self.margins.bottom.0);
+  }
+}
+
+impl Default for PageSettings {
+  fn default() -> PageSettings {
+    PageSettings::new(PageSize::default(), Orientation::default(), DEFAULT_DPI)
+  }
+}
+
+/// A clockwise rotation by a multiple of 90 degrees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+  None,
+  Cw90,
+  Cw180,
+  Cw270
+}
+
+impl Rotation {
+  /// Converts a clockwise angle in degrees, which must be a multiple of 90.
+  pub fn from_degrees(degrees: i32) -> Option<Rotation> {
+    match degrees.rem_euclid(360) {
+      0 => Some(Rotation::None),
+      90 => Some(Rotation::Cw90),
+      180 => Some(Rotation::Cw180),
+      270 => Some(Rotation::Cw270),
+      _ => None
+    }
+  }
+}
+
+/// A rectangle of the source image, in pixels from its top left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32
+}
+
+/// Settings that apply to a single page rather than the whole book.
+#[derive(Debug, Clone, Default)]
+pub struct PageOptions {
+  /// Starts a new section with this title at this page.
+  pub section: Option<String>,
+  /// Text printed on the page below the image.
+  pub caption: Option<String>,
+  /// Rotation applied instead of the automatic landscape rotation.
+  pub rotate: Option<Rotation>,
+  /// Part of the source image to use, applied before rotating.
+  pub crop: Option<Crop>,
+  /// Color handling for this page instead of the book's.
+  pub color: Option<ColorMode>
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn page_size_parses_presets_and_custom_sizes() {
+    assert_eq!("A4".parse::<PageSize>(), Ok(PageSize::A4));
+    assert_eq!("210x297mm".parse::<PageSize>(), Ok(PageSize::Custom(Mm(210.0), Mm(297.0))));
+    assert_eq!("6x9in".parse::<PageSize>(), Ok(PageSize::Custom(Mm(6.0 * MM_PER_INCH), Mm(9.0 * MM_PER_INCH))));
+    assert_eq!("200x200in".parse::<PageSize>(), Ok(PageSize::Custom(MAX_PAGE_LENGTH, MAX_PAGE_LENGTH)));
+  }
+
+  #[test]
+  fn rasters_must_fit_in_memory() {
+    assert!(PageSettings::new(PageSize::Tabloid, Orientation::Landscape, 1200.0).check().is_ok());
+    let poster = PageSize::Custom(Mm(20.0 * MM_PER_INCH), Mm(20.0 * MM_PER_INCH));
+    match PageSettings::new(poster, Orientation::Portrait, 1200.0).check() {
+      Err(BookerError::RasterTooLarge { width: 24000, height: 24000, .. }) => (),
+      other => panic!("unexpected result {:?}", other)
+    }
+  }
+
+  #[test]
+  fn largest_pages_need_a_lower_resolution() {
+    let largest = "200x200in".parse::<PageSize>().unwrap();
+    match PageSettings::new(largest, Orientation::Portrait, 1200.0).check() {
+      Err(BookerError::RasterTooLarge { width: 240000, height: 240000, .. }) => (),
+      other => panic!("unexpected result {:?}", other)
+    }
+    assert!(PageSettings::new(largest, Orientation::Portrait, 30.0).check().is_ok());
+  }
+
+  #[test]
+  fn margins_must_leave_room_for_the_image() {
+    let settings = PageSettings::new(PageSize::A5, Orientation::Portrait, DEFAULT_DPI);
+    assert!(settings.with_margins(Margins::uniform(Mm(70.0))).check().is_ok());
+    assert!(settings.with_margins(Margins::uniform(Mm(74.0))).check().is_err());
+    assert!(settings.with_margins("100,10".parse().unwrap()).check().is_ok());
+    assert!(settings.with_margins("110,10".parse().unwrap()).check().is_err());
+
+    // Pages turned sideways keep their margins
+    let mut settings = settings.with_margins("80,10".parse().unwrap());
+    assert!(settings.check().is_ok());
+    settings.landscape = LandscapePolicy::LandscapePage;
+    match settings.check() {
+      Err(BookerError::MarginsTooLarge { width: Mm(width), height: Mm(height) }) => assert_eq!((width, height), (210.0, 148.0)),
+      other => panic!("unexpected result {:?}", other)
+    }
+  }
+
+  #[test]
+  fn contrasting_color_is_readable() {
+    assert_eq!(PageColor::WHITE.contrasting(), PageColor::BLACK);
+    assert_eq!(PageColor::BLACK.contrasting(), PageColor::WHITE);
+    assert_eq!("#1a1a1a".parse::<PageColor>().unwrap().contrasting(), PageColor::WHITE);
+    assert_eq!("#ffd700".parse::<PageColor>().unwrap().contrasting(), PageColor::BLACK);
+  }
+
+  #[test]
+  fn page_size_rejects_invalid_sizes() {
+    for text in ["b5", "210x297", "0x297mm", "-1x2in", "nanx2in", "201x9in", "1e300x1e300mm", "6x5081mm"] {
+      assert!(text.parse::<PageSize>().is_err(), "{:?} parsed", text);
+    }
+  }
+}
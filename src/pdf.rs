@@ -0,0 +1,333 @@
+use std::io::BufWriter;
+use std::io::Write;
+
+use lopdf::{Object, ObjectId};
+use printpdf::*;
+
+use crate::caption::{caption_column, wrap_caption, CAPTION_BASELINE, CAPTION_FONT_SIZE, CAPTION_LINE_HEIGHT};
+use crate::chapter::Chapter;
+use crate::contents::{entry_baseline, entry_text, ContentsEntry, ENTRY_FONT_SIZE, SIDE_SPACE};
+use crate::cover::{cover_heading, cover_image_settings, Cover, COVER_SIDE, SUBTITLE_FONT_SIZE, TITLE_FONT_SIZE};
+use crate::dates::CaptureTime;
+use crate::encode::{EncodedImage, RasterFilter};
+use crate::error::{BookerError, Result};
+use crate::info::DocumentInfo;
+use crate::outline::{build_outline, write_outline, OutlineGrouping};
+use crate::page::{PageColor, PageSettings};
+use crate::text::MM_PER_POINT;
+
+/// What goes on a single page of the PDF.
+#[derive(Debug, Clone)]
+pub enum PageContent {
+  /// A rendered photo.
+  Raster(EncodedImage),
+  /// A notice in place of an image that could not be used.
+  Missing { path: String, reason: String },
+  /// The page introducing a chapter.
+  Divider(Chapter),
+  /// The title page.
+  Cover(Cover),
+  /// A page of the table of contents; `continued` on all but the first.
+  Contents { entries: Vec<ContentsEntry>, continued: bool }
+}
+
+/// A page of the PDF and the outline and text that go with it.
+#[derive(Debug, Clone)]
+pub struct BookPage {
+  pub content: PageContent,
+  /// Title of the section starting at this page, added as a bookmark.
+  pub section: Option<String>,
+  /// Text printed at the bottom of the page.
+  pub caption: Option<String>,
+  /// The page is turned sideways, see [`PageSettings::sideways`].
+  pub sideways: bool,
+  /// Capture time of the photo, which places the page in the date bookmarks.
+  pub date: Option<CaptureTime>
+}
+
+impl BookPage {
+  /// The settings for this page, with room for its caption and turned
+  /// sideways if it is.
+  fn settings(&self, book: &PageSettings) -> PageSettings {
+    let settings = match &self.caption {
+      Some(caption) => book.with_caption_space(caption),
+      None => *book
+    };
+    if self.sideways { settings.sideways() } else { settings }
+  }
+}
+
+/// Places the rendered pages into a PDF, one per page, with bookmarks grouped
+/// by `outline`, and saves it to `output`. `on_page` is called with each page
+/// number as it is written.
+///
+/// The image data of the pages is moved into the PDF rather than copied, so
+/// that each page image is held in memory at most twice while writing.
+pub fn write_images_to_pdf_file<W: Write, F: FnMut(usize)>(output: W, info: &DocumentInfo, mut pages: Vec<BookPage>,
+                                                            settings: &PageSettings, outline: OutlineGrouping, mut on_page: F) -> Result<()> {
+  let first_settings = pages.first().map_or(*settings, |page| page.settings(settings));
+  let (mut doc, first_page_idx, first_layer_idx) = PdfDocument::new(info.title.as_str(), first_settings.width, first_settings.height, "Layer 1");
+  doc = doc.with_conformance(PdfConformance::Custom(CustomPdfConformance {
+    requires_icc_profile: false,
+    requires_xmp_metadata: false,
+      .. Default::default()
+    }));
+
+  let mut current_page = doc.get_page(first_page_idx);
+  let mut current_layer = current_page.get_layer(first_layer_idx);
+
+  for index in 0..pages.len() {
+    let current_image = index + 1;
+    on_page(current_image);
+    let page = &mut pages[index];
+    let page_settings = page.settings(settings);
+
+    if page_settings.background != PageColor::WHITE {
+      fill_page(&current_layer, &page_settings);
+    }
+
+    match &mut page.content {
+      PageContent::Raster(encoded) => add_raster(&current_layer, encoded, &page_settings),
+      PageContent::Missing { path, reason } => {
+        let font = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+        let top = page_settings.height.0 / 2.0;
+        current_layer.use_text("Missing image", 24.0, Mm(20.0), Mm(top), &font);
+        current_layer.use_text(path.as_str(), 10.0, Mm(20.0), Mm(top - 10.0), &font);
+        current_layer.use_text(reason.as_str(), 10.0, Mm(20.0), Mm(top - 16.0), &font);
+      },
+      PageContent::Cover(cover) => {
+        let title_font = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+        let font = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+        let (title, subtitle) = cover_heading(cover, &page_settings);
+        for (line, baseline) in title {
+          current_layer.use_text(line, TITLE_FONT_SIZE, COVER_SIDE, baseline, &title_font);
+        }
+        if let Some((subtitle, baseline)) = subtitle {
+          current_layer.use_text(subtitle, SUBTITLE_FONT_SIZE, COVER_SIDE, baseline, &font);
+        }
+        if let Some(image) = &mut cover.image {
+          add_raster(&current_layer, image, &cover_image_settings(&page_settings));
+        }
+        if let Some(author) = &cover.author {
+          current_layer.use_text(author.as_str(), 16.0, Mm(20.0), Mm(40.0), &font);
+        }
+        if let Some(dates) = &cover.dates {
+          current_layer.use_text(dates.as_str(), 12.0, Mm(20.0), Mm(30.0), &font);
+        }
+      },
+      PageContent::Contents { entries, continued } => {
+        let title_font = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+        let font = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+        let heading = if *continued { "Contents (continued)" } else { "Contents" };
+        current_layer.use_text(heading, 24.0, Mm(20.0), Mm(page_settings.height.0 - 30.0), &title_font);
+        for (line, entry) in entries.iter().enumerate() {
+          let baseline = entry_baseline(&page_settings, line);
+          let (title, number, number_left) = entry_text(entry, &page_settings);
+          current_layer.use_text(title, ENTRY_FONT_SIZE, SIDE_SPACE, baseline, &font);
+          current_layer.use_text(number, ENTRY_FONT_SIZE, number_left, baseline, &font);
+        }
+      },
+      PageContent::Divider(chapter) => {
+        let title_font = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+        let font = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+        let top = page_settings.height.0 * 0.55;
+        current_layer.use_text(chapter.title(), 28.0, Mm(20.0), Mm(top), &title_font);
+        current_layer.use_text(chapter.date_range(), 14.0, Mm(20.0), Mm(top - 12.0), &font);
+      }
+    }
+
+    if let Some(caption) = &page.caption {
+      let font = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+      // Wrapped to the book's page width, the same as the space kept for it
+      let (x, width) = caption_column(settings);
+      let lines = wrap_caption(caption, width);
+      for (n, line) in lines.iter().enumerate() {
+        let baseline = CAPTION_BASELINE.0 + (lines.len() - 1 - n) as f64 * CAPTION_LINE_HEIGHT.0;
+        current_layer.use_text(line.as_str(), CAPTION_FONT_SIZE, x, Mm(baseline), &font);
+      }
+    }
+
+    if let Some(next_page) = pages.get(index + 1) {
+      let next_settings = next_page.settings(settings);
+      let (page_idx, layer_idx) = doc.add_page(next_settings.width, next_settings.height, format!("Page {}, Layer 1", current_image));
+      current_page = doc.get_page(page_idx);
+      current_layer = current_page.get_layer(layer_idx);
+    }
+  }
+
+  let mut saved = Vec::new();
+  doc.save(&mut BufWriter::new(&mut saved))?;
+  let document = lopdf::Document::load_mem(&saved)?;
+  drop(saved);
+  finish_document(document, info, pages, settings, outline, output)
+}
+
+/// Draws a page raster centered within the margins of `settings`, moving
+/// JPEG data out of `encoded` into the PDF.
+fn add_raster(layer: &PdfLayerReference, encoded: &mut EncodedImage, settings: &PageSettings) {
+  // printpdf can only embed JPEG data, so Flate data is filled in after saving
+  let (image_data, image_filter) = match encoded.filter {
+    RasterFilter::Dct => (std::mem::take(&mut encoded.data), Some(ImageFilter::DCT)),
+    RasterFilter::Flate => (Vec::new(), None)
+  };
+  let image = Image::from(ImageXObject {
+    width: Px(encoded.width as usize),
+    height: Px(encoded.height as usize),
+    color_space: encoded.color_space,
+    bits_per_component: if encoded.bits_per_component == 1 { ColorBits::Bit1 } else { ColorBits::Bit8 },
+    interpolate: encoded.bits_per_component != 1,
+    image_data,
+    image_filter,
+    clipping_bbox: None
+  });
+  let margins = settings.margins;
+  let free_width = settings.width.0 - margins.left.0 - margins.right.0 - settings.raster_length(encoded.width).0;
+  let free_height = settings.height.0 - margins.top.0 - margins.bottom.0 - settings.raster_length(encoded.height).0;
+  let x = Mm(margins.left.0 + free_width / 2.0);
+  let y = Mm(margins.bottom.0 + free_height / 2.0);
+  image.add_to_layer(layer.clone(), Some(x), Some(y), None, Some(settings.image_scale), Some(settings.image_scale), None);
+}
+
+/// Moves the Flate compressed page images that printpdf left empty into
+/// `document`, adds the contents links, bookmarks and document information,
+/// compresses the remaining streams and writes the document to `output`.
+fn finish_document<W: Write>(mut document: lopdf::Document, info: &DocumentInfo, mut pages: Vec<BookPage>, settings: &PageSettings,
+                             outline: OutlineGrouping, mut output: W) -> Result<()> {
+  let page_ids: Vec<ObjectId> = document.get_pages().into_values().collect();
+  for (page, &page_id) in pages.iter_mut().zip(&page_ids) {
+    let encoded = match &mut page.content {
+      PageContent::Raster(encoded) | PageContent::Cover(Cover { image: Some(encoded), .. }) => encoded,
+      _ => continue
+    };
+    if encoded.filter != RasterFilter::Flate {
+      continue;
+    }
+    if let Some(image_id) = page_image(&document, page_id) {
+      let stream = document.get_object_mut(image_id)?.as_stream_mut()?;
+      stream.dict.set("Filter", "FlateDecode");
+      stream.set_content(std::mem::take(&mut encoded.data));
+    }
+  }
+
+  for (page, &page_id) in pages.iter().zip(&page_ids) {
+    if let PageContent::Contents { entries, .. } = &page.content {
+      add_contents_links(&mut document, page_id, entries, &page.settings(settings), &page_ids)?;
+    }
+  }
+
+  write_outline(&mut document, &build_outline(&pages, outline), &page_ids)?;
+  info.write_to(&mut document)?;
+  document.compress();
+  document.save_to(&mut output).map_err(|source| BookerError::Output { source })?;
+  Ok(())
+}
+
+/// Makes each line of a contents page a link to the page it lists.
+fn add_contents_links(document: &mut lopdf::Document, page_id: ObjectId, entries: &[ContentsEntry],
+                      settings: &PageSettings, page_ids: &[ObjectId]) -> Result<()> {
+  let point = |mm: f64| mm / MM_PER_POINT;
+  let mut annotations = Vec::new();
+  for (line, entry) in entries.iter().enumerate() {
+    let target = match page_ids.get(entry.page) {
+      Some(&target) => target,
+      None => continue
+    };
+    let baseline = entry_baseline(settings, line).0;
+    let rect = vec![point(SIDE_SPACE.0).into(), point(baseline - 2.0).into(), point(settings.width.0 - SIDE_SPACE.0).into(), point(baseline + 5.0).into()];
+    let mut link = lopdf::Dictionary::new();
+    link.set("Type", Object::Name(b"Annot".to_vec()));
+    link.set("Subtype", Object::Name(b"Link".to_vec()));
+    link.set("Rect", rect);
+    link.set("Border", vec![0.into(), 0.into(), 0.into()]);
+    link.set("Dest", vec![target.into(), Object::Name(b"Fit".to_vec())]);
+    annotations.push(document.add_object(link).into());
+  }
+  document.get_object_mut(page_id)?.as_dict_mut()?.set("Annots", annotations);
+  Ok(())
+}
+
+/// Finds the image XObject drawn on a page; every page has at most one.
+fn page_image(document: &lopdf::Document, page_id: ObjectId) -> Option<ObjectId> {
+  let (inline, referenced) = document.get_page_resources(page_id);
+  let resources = inline.into_iter().chain(referenced.into_iter().filter_map(|id| document.get_dictionary(id).ok()));
+  for resources in resources {
+    let xobjects = match resources.get(b"XObject").and_then(|o| document.dereference(o)) {
+      Ok((_, Object::Dictionary(xobjects))) => xobjects,
+      _ => continue
+    };
+    for (_, xobject) in xobjects.iter() {
+      let id = match xobject.as_reference() {
+        Ok(id) => id,
+        Err(_) => continue
+      };
+      let is_image = document.get_object(id)
+        .and_then(Object::as_stream)
+        .and_then(|stream| stream.dict.get(b"Subtype"))
+        .and_then(Object::as_name)
+        .is_ok_and(|subtype| subtype == b"Image");
+      if is_image {
+        return Some(id);
+      }
+    }
+  }
+  None
+}
+
+/// Paints the whole page in the background color, leaving black or white,
+/// whichever reads better on it, as the fill color for any text drawn afterwards.
+fn fill_page(layer: &PdfLayerReference, settings: &PageSettings) {
+  let rgb = |color: PageColor| {
+    let channel = |value: u8| value as f64 / 255.0;
+    Color::Rgb(Rgb::new(channel(color.red), channel(color.green), channel(color.blue), None))
+  };
+  let background = settings.background;
+  layer.set_fill_color(rgb(background));
+  layer.add_shape(Line {
+    points: vec![
+      (Point::new(Mm(0.0), Mm(0.0)), false),
+      (Point::new(settings.width, Mm(0.0)), false),
+      (Point::new(settings.width, settings.height), false),
+      (Point::new(Mm(0.0), settings.height), false)
+    ],
+    is_closed: true,
+    has_fill: true,
+    has_stroke: false,
+    is_clipping_path: false
+  });
+  layer.set_fill_color(rgb(background.contrasting()));
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use image::DynamicImage;
+
+  use super::*;
+  use crate::encode::ImageEncoding;
+
+  fn raster_page(encoded: EncodedImage) -> BookPage {
+    BookPage { content: PageContent::Raster(encoded), section: None, caption: None, sideways: false, date: None }
+  }
+
+  #[test]
+  fn page_images_keep_their_compression() {
+    let photo = DynamicImage::ImageLuma8(image::GrayImage::from_fn(40, 30, |x, y| image::Luma([(x * 6 + y) as u8])));
+    let jpeg = EncodedImage::encode(&photo, ImageEncoding::Jpeg, 85, Path::new("a.png")).unwrap();
+    let flate = EncodedImage::encode(&photo, ImageEncoding::Flate, 85, Path::new("b.png")).unwrap();
+    let pages = vec![raster_page(jpeg.clone()), raster_page(flate.clone())];
+
+    let mut pdf = Vec::new();
+    let mut written = Vec::new();
+    write_images_to_pdf_file(&mut pdf, &DocumentInfo::default(), pages, &PageSettings::default(), OutlineGrouping::None, |page| written.push(page)).unwrap();
+    assert_eq!(written, vec![1, 2]);
+
+    let document = lopdf::Document::load_mem(&pdf).unwrap();
+    let page_ids: Vec<ObjectId> = document.get_pages().into_values().collect();
+    for (page_id, (encoded, filter)) in page_ids.into_iter().zip([(jpeg, "DCTDecode"), (flate, "FlateDecode")]) {
+      let image = document.get_object(page_image(&document, page_id).unwrap()).unwrap().as_stream().unwrap();
+      assert_eq!(image.filters().unwrap(), vec![filter]);
+      assert_eq!(image.content, encoded.data);
+    }
+  }
+}
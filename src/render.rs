@@ -0,0 +1,317 @@
+use std::fmt;
+use std::str::FromStr;
+
+use image::imageops;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use image::GenericImageView;
+
+use crate::error::{BookerError, Result};
+use crate::metadata::ImageAndMetadata;
+use crate::page::{FitMode, LandscapePolicy, PageOptions, PageSettings, Rotation};
+
+/// How the colors of a photo are reproduced on its page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMode {
+  /// Keep the photo's colors.
+  Full,
+  /// Convert to shades of gray.
+  #[default]
+  Grayscale,
+  /// Convert to pure black and white, dithered, e.g. for photocopying.
+  Bilevel
+}
+
+impl ColorMode {
+  fn name(self) -> &'static str {
+    match self {
+      ColorMode::Full => "full",
+      ColorMode::Grayscale => "grayscale",
+      ColorMode::Bilevel => "bilevel"
+    }
+  }
+}
+
+impl fmt::Display for ColorMode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+impl FromStr for ColorMode {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::result::Result<ColorMode, String> {
+    [ColorMode::Full, ColorMode::Grayscale, ColorMode::Bilevel].iter()
+      .copied()
+      .find(|mode| mode.name() == s)
+      .ok_or_else(|| format!("unknown color mode {:?}", s))
+  }
+}
+
+/// A rendered page image and the way its page is turned.
+#[derive(Debug, Clone)]
+pub struct PageRaster {
+  pub image: DynamicImage,
+  /// The page is turned sideways, see [`PageSettings::sideways`].
+  pub sideways: bool
+}
+
+/// Loads a source image and turns it into the raster placed on its page.
+pub fn render_page(source: &ImageAndMetadata, settings: &PageSettings, options: &PageOptions) -> Result<PageRaster> {
+  let mut img = image::open(&source.path)
+    .map_err(|e| BookerError::Decode { path: source.path.clone().into(), source: e })?;
+
+  // Everything below, including crops, works on the upright image
+  img = apply_orientation(img, source.orientation);
+
+  if let Some(crop) = options.crop {
+    let fits = |start: u32, length: u32, limit: u32| length > 0 && start.checked_add(length).is_some_and(|end| end <= limit);
+    if !fits(crop.x, crop.width, img.width()) || !fits(crop.y, crop.height, img.height()) {
+      return Err(BookerError::InvalidCrop { path: source.path.clone().into(), crop });
+    }
+    img = img.crop_imm(crop.x, crop.y, crop.width, crop.height);
+  }
+
+  let rgb16 = img.to_rgb8();
+  let (width, height) = rgb16.dimensions();
+  let page_is_landscape = settings.raster_width > settings.raster_height;
+  let working_image = match (options.rotate, settings.landscape) {
+    (Some(Rotation::None), _) => rgb16,
+    (Some(Rotation::Cw90), _) => imageops::rotate90(&rgb16),
+    (Some(Rotation::Cw180), _) => imageops::rotate180(&rgb16),
+    (Some(Rotation::Cw270), _) => imageops::rotate270(&rgb16),
+    // Image is not in the orientation of the page. We may need to rotate it.
+    (None, LandscapePolicy::RotateCcw) if (width > height) != page_is_landscape => imageops::rotate270(&rgb16),
+    (None, LandscapePolicy::RotateCw) if (width > height) != page_is_landscape => imageops::rotate90(&rgb16),
+    (None, _) => rgb16
+  };
+
+  // An image that is still the wrong way up gets a page of its own orientation
+  let (width, height) = working_image.dimensions();
+  let sideways = settings.landscape == LandscapePolicy::LandscapePage && width != height && (width > height) != page_is_landscape;
+  let settings = if sideways { settings.sideways() } else { *settings };
+
+  let color = options.color.unwrap_or(settings.color);
+  let colored_image = match color {
+    ColorMode::Grayscale | ColorMode::Bilevel => DynamicImage::ImageLuma8(imageops::grayscale(&working_image)),
+    ColorMode::Full => DynamicImage::ImageRgb8(working_image)
+  };
+
+  // resize the image to an appropriate size for the page
+  let (raster_width, raster_height) = (settings.raster_width, settings.raster_height);
+  let page_image = match settings.fit {
+    FitMode::Contain => colored_image.resize(raster_width, raster_height, FilterType::CatmullRom),
+    FitMode::Cover => colored_image.resize_to_fill(raster_width, raster_height, FilterType::CatmullRom),
+    FitMode::Stretch => colored_image.resize_exact(raster_width, raster_height, FilterType::CatmullRom)
+  };
+
+  // Dither at the final size so every dot lands on a printed pixel
+  if color == ColorMode::Bilevel {
+    let mut bilevel_image = page_image.to_luma8();
+    imageops::dither(&mut bilevel_image, &imageops::BiLevel);
+    return Ok(PageRaster { image: DynamicImage::ImageLuma8(bilevel_image), sideways });
+  }
+  Ok(PageRaster { image: page_image, sideways })
+}
+
+/// Turns the stored pixels upright according to an EXIF `Orientation` value.
+fn apply_orientation(img: DynamicImage, orientation: u32) -> DynamicImage {
+  match orientation {
+    2 => img.fliph(),
+    3 => img.rotate180(),
+    4 => img.flipv(),
+    // Mirrored along the top-left to bottom-right diagonal
+    5 => img.rotate90().fliph(),
+    6 => img.rotate90(),
+    // Mirrored along the top-right to bottom-left diagonal
+    7 => img.rotate270().fliph(),
+    8 => img.rotate270(),
+    _ => img
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use chrono::NaiveDate;
+  use image::ColorType;
+  use printpdf::{ColorSpace, Mm};
+
+  use super::*;
+  use crate::encode::{EncodedImage, ImageEncoding};
+  use crate::page::{Crop, Orientation, PageSize, DEFAULT_DPI};
+  use crate::test_support::{image, write_png, TempDir};
+
+  /// A small page, so the rasters stay quick to render.
+  fn small_page() -> PageSettings {
+    PageSettings::new(PageSize::Custom(Mm(40.0), Mm(60.0)), Orientation::Portrait, DEFAULT_DPI)
+  }
+
+  /// Writes a `width` × `height` photo into `dir` and returns its metadata.
+  fn photo(dir: &TempDir, width: u32, height: u32, color: [u8; 3]) -> ImageAndMetadata {
+    let path = dir.join(format!("photo-{}x{}.png", width, height));
+    write_png(&path, width, height, color);
+    image(&path.display().to_string(), NaiveDate::from_ymd(2021, 3, 14).and_hms(10, 15, 0))
+  }
+
+  fn render_in(source: &ImageAndMetadata, color: ColorMode) -> DynamicImage {
+    render_page(source, &small_page(), &PageOptions { color: Some(color), ..PageOptions::default() }).unwrap().image
+  }
+
+  #[test]
+  fn color_modes_choose_the_pixel_format() {
+    let dir = TempDir::new();
+    let source = photo(&dir, 40, 60, [200, 40, 40]);
+
+    let full = render_in(&source, ColorMode::Full);
+    assert_eq!(full.color(), ColorType::Rgb8);
+    assert_eq!(full.get_pixel(5, 5).0[..3], [200, 40, 40]);
+    let encoded = EncodedImage::encode(&full, ImageEncoding::Flate, 85, Path::new("photo.png")).unwrap();
+    assert!(matches!(encoded.color_space, ColorSpace::Rgb));
+
+    let gray = render_in(&source, ColorMode::Grayscale);
+    assert_eq!(gray.color(), ColorType::L8);
+    let luma = gray.as_luma8().unwrap();
+    assert!(luma.pixels().all(|p| p[0] == luma.get_pixel(0, 0)[0]));
+    let encoded = EncodedImage::encode(&gray, ImageEncoding::Flate, 85, Path::new("photo.png")).unwrap();
+    assert!(matches!((encoded.color_space, encoded.bits_per_component), (ColorSpace::Greyscale, 8)));
+
+    let bilevel = render_in(&source, ColorMode::Bilevel);
+    let dots = bilevel.as_luma8().unwrap();
+    assert!(dots.pixels().all(|p| p[0] == 0 || p[0] == 255));
+    assert!(dots.pixels().any(|p| p[0] == 0) && dots.pixels().any(|p| p[0] == 255));
+    let encoded = EncodedImage::encode(&bilevel, ImageEncoding::Auto, 85, Path::new("photo.png")).unwrap();
+    assert!(matches!((encoded.color_space, encoded.bits_per_component), (ColorSpace::Greyscale, 1)));
+  }
+
+  #[test]
+  fn pages_override_the_book_color_mode() {
+    let dir = TempDir::new();
+    let source = photo(&dir, 40, 60, [200, 40, 40]);
+    let mut settings = small_page();
+    settings.color = ColorMode::Full;
+    assert_eq!(render_page(&source, &settings, &PageOptions::default()).unwrap().image.color(), ColorType::Rgb8);
+    let options = PageOptions { color: Some(ColorMode::Grayscale), ..PageOptions::default() };
+    assert_eq!(render_page(&source, &settings, &options).unwrap().image.color(), ColorType::L8);
+  }
+
+  /// The rows of a grayscale image, top to bottom.
+  fn rows(img: &DynamicImage) -> Vec<Vec<u8>> {
+    let gray = img.to_luma8();
+    gray.rows().map(|row| row.map(|p| p[0]).collect()).collect()
+  }
+
+  #[test]
+  fn orientation_turns_photos_upright() {
+    // Stored as 2 × 3 pixels, numbered row by row
+    let stored = DynamicImage::ImageLuma8(image::GrayImage::from_raw(2, 3, vec![1, 2, 3, 4, 5, 6]).unwrap());
+    let expected: [(u32, Vec<Vec<u8>>); 8] = [
+      (1, vec![vec![1, 2], vec![3, 4], vec![5, 6]]),
+      (2, vec![vec![2, 1], vec![4, 3], vec![6, 5]]),
+      (3, vec![vec![6, 5], vec![4, 3], vec![2, 1]]),
+      (4, vec![vec![5, 6], vec![3, 4], vec![1, 2]]),
+      (5, vec![vec![1, 3, 5], vec![2, 4, 6]]),
+      (6, vec![vec![5, 3, 1], vec![6, 4, 2]]),
+      (7, vec![vec![6, 4, 2], vec![5, 3, 1]]),
+      (8, vec![vec![2, 4, 6], vec![1, 3, 5]])
+    ];
+    for (orientation, upright) in expected {
+      assert_eq!(rows(&apply_orientation(stored.clone(), orientation)), upright, "orientation {}", orientation);
+    }
+    // Values outside 1 to 8 leave the image alone
+    assert_eq!(rows(&apply_orientation(stored.clone(), 0)), rows(&stored));
+    assert_eq!(rows(&apply_orientation(stored.clone(), 9)), rows(&stored));
+  }
+
+  #[test]
+  fn crops_apply_to_the_upright_photo() {
+    let dir = TempDir::new();
+    let mut source = photo(&dir, 60, 40, [90, 90, 90]);
+    // Stored sideways, so the upright photo is 40 × 60
+    source.orientation = 6;
+    let options = PageOptions { crop: Some(Crop { x: 0, y: 30, width: 40, height: 30 }), ..PageOptions::default() };
+    assert!(render_page(&source, &small_page(), &options).is_ok());
+    source.orientation = 1;
+    assert!(render_page(&source, &small_page(), &options).is_err());
+  }
+
+  /// Renders a landscape photo, dark on its left half and light on its
+  /// right, on a portrait page with the given policy.
+  fn render_landscape(dir: &TempDir, landscape: LandscapePolicy) -> PageRaster {
+    let path = dir.join("landscape.png");
+    image::GrayImage::from_fn(60, 40, |x, _| image::Luma([if x < 30 { 0 } else { 255 }])).save(&path).unwrap();
+    let source = image(&path.display().to_string(), NaiveDate::from_ymd(2021, 3, 14).and_hms(10, 15, 0));
+    let mut settings = small_page();
+    settings.landscape = landscape;
+    render_page(&source, &settings, &PageOptions::default()).unwrap()
+  }
+
+  /// Brightness of the top left and bottom right corners.
+  fn corners(raster: &PageRaster) -> (u8, u8) {
+    let gray = raster.image.to_luma8();
+    (gray.get_pixel(2, 2)[0], gray.get_pixel(gray.width() - 3, gray.height() - 3)[0])
+  }
+
+  #[test]
+  fn landscape_photos_follow_the_policy() {
+    let dir = TempDir::new();
+    let page = small_page();
+
+    // Counterclockwise, the left edge of the photo ends up at the bottom
+    let raster = render_landscape(&dir, LandscapePolicy::RotateCcw);
+    assert!(!raster.sideways);
+    assert_eq!(raster.image.dimensions(), (page.raster_width, page.raster_height));
+    assert_eq!(corners(&raster), (255, 0));
+
+    let raster = render_landscape(&dir, LandscapePolicy::RotateCw);
+    assert!(!raster.sideways);
+    assert_eq!(corners(&raster), (0, 255));
+
+    // A page of its own, turned sideways, with the photo upright
+    let raster = render_landscape(&dir, LandscapePolicy::LandscapePage);
+    assert!(raster.sideways);
+    assert_eq!(raster.image.dimensions(), (page.raster_height, page.raster_width));
+    assert_eq!(corners(&raster), (0, 255));
+
+    // Upright and scaled down to the width of the portrait page
+    let raster = render_landscape(&dir, LandscapePolicy::FitPortrait);
+    assert!(!raster.sideways);
+    let (width, height) = raster.image.dimensions();
+    assert_eq!(width, page.raster_width);
+    assert!(height < width);
+  }
+
+  #[test]
+  fn explicit_rotation_overrides_the_policy() {
+    let dir = TempDir::new();
+    let source = photo(&dir, 60, 40, [90, 90, 90]);
+    let mut settings = small_page();
+    settings.landscape = LandscapePolicy::LandscapePage;
+    let options = PageOptions { rotate: Some(Rotation::Cw90), ..PageOptions::default() };
+    assert!(!render_page(&source, &settings, &options).unwrap().sideways);
+    let options = PageOptions { rotate: Some(Rotation::None), ..PageOptions::default() };
+    assert!(render_page(&source, &settings, &options).unwrap().sideways);
+  }
+
+  #[test]
+  fn crops_must_lie_inside_the_image() {
+    let dir = TempDir::new();
+    let source = photo(&dir, 40, 30, [90, 90, 90]);
+    let render = |crop: Crop| render_page(&source, &PageSettings::default(), &PageOptions { crop: Some(crop), ..PageOptions::default() });
+
+    assert!(render(Crop { x: 10, y: 0, width: 30, height: 30 }).is_ok());
+    for crop in [
+      Crop { x: 30, y: 0, width: 20, height: 10 },
+      Crop { x: 0, y: 25, width: 10, height: 10 },
+      Crop { x: u32::MAX, y: 0, width: 2, height: 10 },
+      Crop { x: 0, y: 0, width: 0, height: 10 }
+    ] {
+      match render(crop) {
+        Err(BookerError::InvalidCrop { crop: rejected, .. }) => assert_eq!(rejected, crop),
+        other => panic!("crop {:?} gave {:?}", crop, other.map(|_| ()))
+      }
+    }
+  }
+}
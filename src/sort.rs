@@ -0,0 +1,191 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+
+use crate::metadata::ImageAndMetadata;
+
+/// How the pages of a book are ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+  /// By capture time, after time zones and clock corrections are applied.
+  #[default]
+  ExifDate,
+  /// By file name, comparing runs of digits as numbers (`scan2` before `scan10`).
+  FilenameNatural,
+  /// By the modification time recorded by the filesystem.
+  Mtime,
+  /// In the order the inputs were listed; directories contribute their files in
+  /// natural file name order.
+  Manifest,
+  /// In whatever order the files were found.
+  Unsorted
+}
+
+impl SortOrder {
+  fn name(self) -> &'static str {
+    match self {
+      SortOrder::ExifDate => "exif-date",
+      SortOrder::FilenameNatural => "filename-natural",
+      SortOrder::Mtime => "mtime",
+      SortOrder::Manifest => "manifest",
+      SortOrder::Unsorted => "none"
+    }
+  }
+}
+
+impl fmt::Display for SortOrder {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+impl FromStr for SortOrder {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<SortOrder, String> {
+    [SortOrder::ExifDate, SortOrder::FilenameNatural, SortOrder::Mtime, SortOrder::Manifest, SortOrder::Unsorted].iter()
+      .copied()
+      .find(|order| order.name() == s)
+      .ok_or_else(|| format!("unknown sort order {:?}", s))
+  }
+}
+
+/// One run of a name, as compared by [`NaturalKey`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Chunk {
+  /// Digits without leading zeros, ordered by length first so that no parsing is needed.
+  Number(usize, String),
+  Text(String)
+}
+
+/// Sort key that orders names the way people read them: `scan2` before `scan10`,
+/// ignoring case. Names that only differ in case or leading zeros are ordered
+/// by their plain text.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NaturalKey(Vec<Chunk>, String);
+
+impl NaturalKey {
+  pub fn new(name: &str) -> NaturalKey {
+    let mut chunks = Vec::new();
+    let mut rest = name;
+    while let Some(first) = rest.chars().next() {
+      let is_digit = first.is_ascii_digit();
+      let end = rest.find(|c: char| c.is_ascii_digit() != is_digit).unwrap_or(rest.len());
+      let (run, tail) = rest.split_at(end);
+      chunks.push(if is_digit {
+        let digits = run.trim_start_matches('0');
+        Chunk::Number(digits.len(), digits.to_string())
+      } else {
+        Chunk::Text(run.to_lowercase())
+      });
+      rest = tail;
+    }
+    NaturalKey(chunks, name.to_string())
+  }
+}
+
+/// Compares two names in natural order, see [`NaturalKey`].
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+  NaturalKey::new(a).cmp(&NaturalKey::new(b))
+}
+
+/// Natural key of the file name of `path`, with the full path as the final tiebreak.
+pub(crate) fn file_name_key(path: &str) -> (NaturalKey, String) {
+  let name = Path::new(path).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+  (NaturalKey::new(&name), path.to_string())
+}
+
+/// What a page is sorted by. Only keys built for the same [`SortOrder`] are compared.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum SortKey {
+  Capture(Option<DateTime<Utc>>, (NaturalKey, String)),
+  Name((NaturalKey, String)),
+  Mtime(Option<SystemTime>, (NaturalKey, String)),
+  Unchanged
+}
+
+/// Builds the key for a page showing `image`, or a placeholder for `path` when
+/// the image could not be read.
+pub(crate) fn sort_key(order: SortOrder, image: Option<&ImageAndMetadata>, path: &str) -> SortKey {
+  match order {
+    // Shots within the same instant keep their file name order
+    SortOrder::ExifDate => SortKey::Capture(image.map(|i| i.instant()), file_name_key(path)),
+    SortOrder::FilenameNatural => SortKey::Name(file_name_key(path)),
+    SortOrder::Mtime => {
+      let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+      SortKey::Mtime(modified, file_name_key(path))
+    },
+    SortOrder::Manifest | SortOrder::Unsorted => SortKey::Unchanged
+  }
+}
+
+/// Puts `images` into the given order, newest or last first if `reverse` is set.
+pub fn sort_images(images: &mut [ImageAndMetadata], order: SortOrder, reverse: bool) {
+  images.sort_by_cached_key(|image| sort_key(order, Some(image), &image.path));
+  if reverse {
+    images.reverse();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::NaiveDate;
+
+  use super::*;
+  use crate::test_support::image;
+
+  fn paths(images: &[ImageAndMetadata]) -> Vec<&str> {
+    images.iter().map(|image| image.path.as_str()).collect()
+  }
+
+  #[test]
+  fn natural_order_compares_numbers() {
+    assert_eq!(natural_cmp("scan2", "scan10"), Ordering::Less);
+    assert_eq!(natural_cmp("scan10", "scan9"), Ordering::Greater);
+    assert_eq!(natural_cmp("page 3b", "page 3a"), Ordering::Greater);
+    assert_eq!(natural_cmp("99", "scan"), Ordering::Less);
+  }
+
+  #[test]
+  fn natural_order_ignores_case_and_leading_zeros() {
+    assert_eq!(natural_cmp("Scan2", "scan10"), Ordering::Less);
+    assert_eq!(natural_cmp("scan007", "scan10"), Ordering::Less);
+    assert_eq!(natural_cmp("scan0", "scan00"), Ordering::Less);
+    // Equal apart from leading zeros or case, the plain text decides
+    assert_eq!(natural_cmp("scan02", "scan2"), Ordering::Less);
+    assert_eq!(natural_cmp("Scan2", "scan2"), Ordering::Less);
+    assert_eq!(natural_cmp("scan2", "scan2"), Ordering::Equal);
+  }
+
+  #[test]
+  fn file_name_key_uses_the_name_only() {
+    assert!(file_name_key("b/scan2.jpg") < file_name_key("a/scan10.jpg"));
+    assert!(file_name_key("a/scan2.jpg") < file_name_key("b/scan2.jpg"));
+  }
+
+  #[test]
+  fn burst_shots_follow_their_fractions() {
+    let second = NaiveDate::from_ymd(2021, 3, 14).and_hms(10, 15, 0);
+    let mut images = vec![
+      image("burst/IMG_0003.jpg", second + chrono::Duration::milliseconds(100)),
+      image("burst/IMG_0001.jpg", second + chrono::Duration::milliseconds(250)),
+      image("burst/IMG_0002.jpg", second)
+    ];
+    sort_images(&mut images, SortOrder::ExifDate, false);
+    assert_eq!(paths(&images), vec!["burst/IMG_0002.jpg", "burst/IMG_0003.jpg", "burst/IMG_0001.jpg"]);
+    sort_images(&mut images, SortOrder::ExifDate, true);
+    assert_eq!(paths(&images), vec!["burst/IMG_0001.jpg", "burst/IMG_0003.jpg", "burst/IMG_0002.jpg"]);
+  }
+
+  #[test]
+  fn shots_in_the_same_instant_follow_their_file_names() {
+    let second = NaiveDate::from_ymd(2021, 3, 14).and_hms(10, 15, 0);
+    let mut images = vec![image("a/IMG_10.jpg", second), image("b/IMG_9.jpg", second), image("c/IMG_2.jpg", second)];
+    sort_images(&mut images, SortOrder::ExifDate, false);
+    assert_eq!(paths(&images), vec!["c/IMG_2.jpg", "b/IMG_9.jpg", "a/IMG_10.jpg"]);
+  }
+}
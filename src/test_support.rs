@@ -0,0 +1,103 @@
+//! Fixtures shared by the unit tests.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use image::{ImageBuffer, Rgb};
+
+use crate::dates::{CaptureTime, DateSource};
+use crate::metadata::ImageAndMetadata;
+use crate::pdf::{BookPage, PageContent};
+
+/// A fresh directory below the system temp directory, removed with
+/// everything in it when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+  pub fn new() -> TempDir {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let name = format!("wckfa-booker-test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+    let path = std::env::temp_dir().join(name);
+    fs::create_dir_all(&path).unwrap();
+    TempDir(path)
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.0
+  }
+
+  pub fn join<P: AsRef<Path>>(&self, name: P) -> PathBuf {
+    self.0.join(name)
+  }
+}
+
+impl Drop for TempDir {
+  fn drop(&mut self) {
+    fs::remove_dir_all(&self.0).ok();
+  }
+}
+
+/// Writes a `width` × `height` PNG filled with `color` to `path`.
+pub fn write_png(path: &Path, width: u32, height: u32, color: [u8; 3]) {
+  ImageBuffer::from_pixel(width, height, Rgb(color)).save(path).unwrap();
+}
+
+/// Metadata of a photo at `path` taken at `date` by a camera without a time zone.
+pub fn image(path: &str, date: NaiveDateTime) -> ImageAndMetadata {
+  ImageAndMetadata {
+    path: path.to_string(),
+    date_created: date,
+    date_source: DateSource::ExifOriginal,
+    date_zoned: None,
+    camera_model: None,
+    description: None,
+    orientation: 1
+  }
+}
+
+/// A time on `day` of March 2024.
+pub fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+  NaiveDate::from_ymd(2024, 3, day).and_hms(hour, minute, 0)
+}
+
+/// A page of the book without a photo, dated `date` by a camera set to UTC.
+pub fn page(date: Option<NaiveDateTime>) -> BookPage {
+  let content = PageContent::Missing { path: String::new(), reason: String::new() };
+  let date = date.map(|local| CaptureTime { local, instant: DateTime::from_utc(local, Utc) });
+  BookPage { content, section: None, caption: None, sideways: false, date }
+}
+
+/// A page dated `local` by a camera set to a time zone `utc_offset_hours`
+/// ahead of UTC.
+pub fn zoned_page(local: NaiveDateTime, utc_offset_hours: i64) -> BookPage {
+  let mut page = page(Some(local));
+  page.date = Some(CaptureTime { local, instant: DateTime::from_utc(local - Duration::hours(utc_offset_hours), Utc) });
+  page
+}
+
+/// A writer whose bytes can still be read after it has been handed over as
+/// an [`OutputSink::Writer`](crate::OutputSink::Writer).
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+  pub fn bytes(&self) -> Vec<u8> {
+    self.0.borrow().clone()
+  }
+}
+
+impl Write for SharedBuffer {
+  fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+    self.0.borrow_mut().extend_from_slice(data);
+    Ok(data.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
@@ -0,0 +1,89 @@
+//! Measuring and line breaking of text set in the built-in Helvetica fonts.
+
+use printpdf::Mm;
+
+/// Length of a typographic point.
+pub(crate) const MM_PER_POINT: f64 = 25.4 / 72.0;
+
+/// How much wider Helvetica Bold can run than [`text_width`] measures for
+/// regular Helvetica; scale the font size by it to measure bold text.
+pub(crate) const BOLD_WIDTH: f64 = 1.1;
+
+/// Approximate printed width of `text` in Helvetica at `font_size` points, in
+/// millimetres, erring on the wide side. Digits are measured exactly, so
+/// numbers can be right aligned.
+pub(crate) fn text_width(text: &str, font_size: f64) -> f64 {
+  let ems: f64 = text.chars().map(|c| match c {
+    ' ' | '.' | ',' | ':' | ';' | '!' | '\'' | '|' | 'i' | 'j' | 'l' | 'I' => 0.278,
+    'f' | 't' | 'r' | '(' | ')' | '[' | ']' | '-' | '"' => 0.333,
+    'm' | 'M' => 0.833,
+    'w' | '%' => 0.889,
+    'W' | '@' | '—' => 1.0,
+    'A'..='Z' => 0.778,
+    _ => 0.556
+  }).sum();
+  ems * font_size * MM_PER_POINT
+}
+
+/// Breaks `text` into lines that fit `width` at `font_size`, at spaces where
+/// possible, cutting it short with an ellipsis after `max_lines`.
+pub(crate) fn wrap_text(text: &str, width: Mm, font_size: f64, max_lines: usize) -> Vec<String> {
+  let fits = |line: &str| text_width(line, font_size) <= width.0;
+  let mut lines: Vec<String> = Vec::new();
+  let mut line = String::new();
+  for word in text.split_whitespace() {
+    let joined = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+    if fits(&joined) {
+      line = joined;
+      continue;
+    }
+    if !line.is_empty() {
+      lines.push(std::mem::take(&mut line));
+    }
+    // A word wider than the whole line is broken wherever it has to be
+    line = word.to_string();
+    while !fits(&line) {
+      let split = line.char_indices()
+        .map(|(i, c)| i + c.len_utf8())
+        .take_while(|&end| fits(&line[..end]))
+        .last()
+        .unwrap_or_else(|| line.chars().next().map_or(0, char::len_utf8));
+      lines.push(line[..split].to_string());
+      line = line[split..].to_string();
+    }
+  }
+  if !line.is_empty() {
+    lines.push(line);
+  }
+
+  if lines.len() > max_lines {
+    lines.truncate(max_lines);
+    if let Some(last) = lines.last_mut() {
+      while !last.is_empty() && !fits(&format!("{}…", last)) {
+        last.pop();
+      }
+      *last = format!("{}…", last.trim_end());
+    }
+  }
+  lines
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn digits_share_one_width() {
+    assert_eq!(text_width("1", 12.0), text_width("8", 12.0));
+    assert!((text_width("100", 12.0) - 3.0 * text_width("0", 12.0)).abs() < 1e-9);
+    assert_eq!(text_width("0", 24.0), 2.0 * text_width("0", 12.0));
+  }
+
+  #[test]
+  fn a_single_line_is_cut_short() {
+    let lines = wrap_text("Saturday, 16 March 2024 to Sunday, 17 March 2024", Mm(40.0), 12.0, 1);
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].ends_with('…'));
+    assert!(text_width(&lines[0], 12.0) <= 40.0);
+  }
+}